@@ -0,0 +1,196 @@
+// ast.rs
+// Abstract syntax tree produced by the parser
+
+use crate::token::Span;
+use std::fmt;
+
+/// Binary operators. Comparisons produce 1 or 0 when used as a value.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    EqEq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+}
+
+impl BinOp {
+    /// The operator as it is written in Teeny (and C)
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+            BinOp::EqEq => "==",
+            BinOp::NotEq => "!=",
+            BinOp::Lt => "<",
+            BinOp::LtEq => "<=",
+            BinOp::Gt => ">",
+            BinOp::GtEq => ">=",
+        }
+    }
+
+    pub fn is_comparison(&self) -> bool {
+        !matches!(self, BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div)
+    }
+
+    /// Binding strength, higher binds tighter.
+    pub fn precedence(&self) -> u8 {
+        match self {
+            BinOp::Mul | BinOp::Div => 3,
+            BinOp::Add | BinOp::Sub => 2,
+            _ => 1,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum UnaryOp {
+    Plus,
+    Minus,
+}
+
+impl UnaryOp {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            UnaryOp::Plus => "+",
+            UnaryOp::Minus => "-",
+        }
+    }
+}
+
+/// Every expression ends with the span of its source. A folded one has
+/// the span of the expression it replaced.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    /// A number literal, kept as written in the source.
+    Number(String, Span),
+    Var(String, Span),
+    Unary(UnaryOp, Box<Expr>, Span),
+    Binary(BinOp, Box<Expr>, Box<Expr>, Span),
+}
+
+impl Expr {
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Number(_, span) | Expr::Var(_, span) => *span,
+            Expr::Unary(_, _, span) | Expr::Binary(_, _, _, span) => *span,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Printable {
+    String(String),
+    Expr(Expr),
+}
+
+/// Every statement has the span of its source, from its first token to
+/// the end of its last one (ENDIF or ENDWHILE for blocks).
+#[derive(Clone, Debug, PartialEq)]
+pub enum Statement {
+    Print { value: Printable, newline: bool, span: Span },
+    If { condition: Expr, body: Vec<Statement>, span: Span },
+    While { condition: Expr, body: Vec<Statement>, span: Span },
+    /// A label, with the `##` comments written just before it
+    Label { name: String, doc: Option<String>, span: Span },
+    Goto { name: String, span: Span },
+    Let { name: String, value: Expr, span: Span },
+    Input { name: String, span: Span },
+}
+
+impl Statement {
+    pub fn span(&self) -> Span {
+        match self {
+            Statement::Print { span, .. }
+            | Statement::If { span, .. }
+            | Statement::While { span, .. }
+            | Statement::Label { span, .. }
+            | Statement::Goto { span, .. }
+            | Statement::Let { span, .. }
+            | Statement::Input { span, .. } => *span,
+        }
+    }
+}
+
+/// The floating point type that holds every Teeny value
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum NumberType {
+    /// C's float
+    #[default]
+    F32,
+    /// C's double
+    F64,
+}
+
+impl NumberType {
+    /// The name used with --number-type
+    pub fn by_name(name: &str) -> Option<NumberType> {
+        match name {
+            "f32" => Some(NumberType::F32),
+            "f64" => Some(NumberType::F64),
+            _ => None,
+        }
+    }
+
+    /// The value of a number literal. An f32 is read straight from the
+    /// text, since going through f64 could round twice.
+    pub fn parse(&self, text: &str) -> Option<f64> {
+        match self {
+            NumberType::F32 => text.parse::<f32>().ok().map(f64::from),
+            NumberType::F64 => text.parse().ok(),
+        }
+    }
+
+    /// Round the result of an f64 operation to this type. For +, -, * and
+    /// / on f32 values this gives the same answer as f32 arithmetic.
+    pub fn round(&self, value: f64) -> f64 {
+        match self {
+            NumberType::F32 => value as f32 as f64,
+            NumberType::F64 => value,
+        }
+    }
+
+    /// Bytes in a value of this type
+    pub fn size(&self) -> usize {
+        match self {
+            NumberType::F32 => 4,
+            NumberType::F64 => 8,
+        }
+    }
+
+    /// The shortest decimal that reads back as `value`
+    pub fn text(&self, value: f64) -> String {
+        match self {
+            NumberType::F32 => format!("{:?}", value as f32),
+            NumberType::F64 => format!("{:?}", value),
+        }
+    }
+}
+
+/// A whole Teeny program.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Program {
+    pub statements: Vec<Statement>,
+    /// Every variable assigned by LET or INPUT, in order of first appearance.
+    pub variables: Vec<String>,
+    pub number_type: NumberType,
+}
+
+/// Prints an expression the way it would be written in Teeny
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Number(n, _) => write!(f, "{}", n),
+            Expr::Var(v, _) => write!(f, "{}", v),
+            Expr::Unary(op, inner, _) => write!(f, "{}{}", op.symbol(), inner),
+            Expr::Binary(op, l, r, _) => write!(f, "{} {} {}", l, op.symbol(), r),
+        }
+    }
+}
@@ -0,0 +1,170 @@
+// astdump.rs
+// Dumps of the AST for --emit=ast (an indented tree) and --emit=ast-json
+// (a versioned JSON document for tools written in other languages).
+
+use crate::ast::{Expr, Printable, Program, Statement};
+use crate::token::Span;
+
+/// Bumped whenever the JSON changes in a way that could break a reader
+pub const AST_JSON_VERSION: u32 = 2;
+
+/// An indented listing of the program
+pub fn tree(program: &Program) -> String {
+    let mut out = String::from("Program\n");
+    out.push_str(&format!("  variables: {}\n", program.variables.join(", ")));
+    for stmt in program.statements.iter() {
+        tree_statement(stmt, 1, &mut out);
+    }
+    out
+}
+
+fn line(out: &mut String, depth: usize, text: &str) {
+    out.push_str(&format!("{:indent$}{}\n", "", text, indent = depth * 2));
+}
+
+fn location(span: Span) -> String {
+    format!("@{}:{} ({}..{})", span.line, span.column, span.start, span.end)
+}
+
+fn tree_statement(stmt: &Statement, depth: usize, out: &mut String) {
+    let at = location(stmt.span());
+    match stmt {
+        Statement::Print { value, newline, .. } => {
+            let keyword = if *newline { "PrintLn" } else { "Print" };
+            match value {
+                Printable::String(text) => line(out, depth, &format!("{} {:?} {}", keyword, text, at)),
+                Printable::Expr(e) => {
+                    line(out, depth, &format!("{} {}", keyword, at));
+                    tree_expr(e, depth + 1, out);
+                }
+            }
+        }
+        Statement::If { condition, body, .. } | Statement::While { condition, body, .. } => {
+            let keyword = if matches!(stmt, Statement::If { .. }) { "If" } else { "While" };
+            line(out, depth, &format!("{} {}", keyword, at));
+            line(out, depth + 1, "condition:");
+            tree_expr(condition, depth + 2, out);
+            line(out, depth + 1, "body:");
+            for s in body.iter() {
+                tree_statement(s, depth + 2, out);
+            }
+        }
+        Statement::Label { name, doc, .. } => {
+            line(out, depth, &format!("Label {} {}", name, at));
+            if let Some(doc) = doc {
+                line(out, depth + 1, &format!("doc: {:?}", doc));
+            }
+        }
+        Statement::Goto { name, .. } => line(out, depth, &format!("Goto {} {}", name, at)),
+        Statement::Let { name, value, .. } => {
+            line(out, depth, &format!("Let {} {}", name, at));
+            tree_expr(value, depth + 1, out);
+        }
+        Statement::Input { name, .. } => line(out, depth, &format!("Input {} {}", name, at)),
+    }
+}
+
+fn tree_expr(e: &Expr, depth: usize, out: &mut String) {
+    match e {
+        Expr::Number(n, _) => line(out, depth, &format!("Number {}", n)),
+        Expr::Var(v, _) => line(out, depth, &format!("Var {}", v)),
+        Expr::Unary(op, inner, _) => {
+            line(out, depth, &format!("Unary {}", op.symbol()));
+            tree_expr(inner, depth + 1, out);
+        }
+        Expr::Binary(op, l, r, _) => {
+            line(out, depth, &format!("Binary {}", op.symbol()));
+            tree_expr(l, depth + 1, out);
+            tree_expr(r, depth + 1, out);
+        }
+    }
+}
+
+/// The program as JSON. Every node is an object with a "kind"; statements
+/// and expressions also have a "span" with byte offsets and the line and
+/// column where they start.
+pub fn json(program: &Program) -> String {
+    let variables: Vec<String> = program.variables.iter().map(|v| string(v)).collect();
+    format!(
+        "{{\"format\":\"ttrust-ast\",\"version\":{},\"variables\":[{}],\"statements\":{}}}\n",
+        AST_JSON_VERSION,
+        variables.join(","),
+        json_statements(&program.statements)
+    )
+}
+
+fn json_statements(statements: &[Statement]) -> String {
+    let items: Vec<String> = statements.iter().map(json_statement).collect();
+    format!("[{}]", items.join(","))
+}
+
+fn json_statement(stmt: &Statement) -> String {
+    let span = stmt.span();
+    let fields = match stmt {
+        Statement::Print { value, newline, .. } => {
+            let value = match value {
+                Printable::String(text) => format!("{{\"kind\":\"String\",\"value\":{}}}", string(text)),
+                Printable::Expr(e) => json_expr(e),
+            };
+            format!("\"kind\":\"Print\",\"newline\":{},\"value\":{}", newline, value)
+        }
+        Statement::If { condition, body, .. } => {
+            format!("\"kind\":\"If\",\"condition\":{},\"body\":{}", json_expr(condition), json_statements(body))
+        }
+        Statement::While { condition, body, .. } => {
+            format!("\"kind\":\"While\",\"condition\":{},\"body\":{}", json_expr(condition), json_statements(body))
+        }
+        Statement::Label { name, doc, .. } => {
+            let doc = doc.as_deref().map(string).unwrap_or_else(|| String::from("null"));
+            format!("\"kind\":\"Label\",\"name\":{},\"doc\":{}", string(name), doc)
+        }
+        Statement::Goto { name, .. } => format!("\"kind\":\"Goto\",\"name\":{}", string(name)),
+        Statement::Let { name, value, .. } => {
+            format!("\"kind\":\"Let\",\"name\":{},\"value\":{}", string(name), json_expr(value))
+        }
+        Statement::Input { name, .. } => format!("\"kind\":\"Input\",\"name\":{}", string(name)),
+    };
+    format!("{{{},\"span\":{}}}", fields, json_span(span))
+}
+
+fn json_expr(e: &Expr) -> String {
+    let fields = match e {
+        // The value as the compiler reads it: decimal, without underscores.
+        Expr::Number(n, _) => format!("\"kind\":\"Number\",\"value\":{}", string(n)),
+        Expr::Var(v, _) => format!("\"kind\":\"Var\",\"name\":{}", string(v)),
+        Expr::Unary(op, inner, _) => {
+            format!("\"kind\":\"Unary\",\"op\":{},\"operand\":{}", string(op.symbol()), json_expr(inner))
+        }
+        Expr::Binary(op, l, r, _) => format!(
+            "\"kind\":\"Binary\",\"op\":{},\"left\":{},\"right\":{}",
+            string(op.symbol()),
+            json_expr(l),
+            json_expr(r)
+        ),
+    };
+    format!("{{{},\"span\":{}}}", fields, json_span(e.span()))
+}
+
+fn json_span(span: Span) -> String {
+    format!(
+        "{{\"start\":{},\"end\":{},\"line\":{},\"column\":{}}}",
+        span.start, span.end, span.line, span.column
+    )
+}
+
+/// A JSON string literal
+fn string(text: &str) -> String {
+    let mut s = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => s.push_str("\\\""),
+            '\\' => s.push_str("\\\\"),
+            '\n' => s.push_str("\\n"),
+            '\t' => s.push_str("\\t"),
+            c if (c as u32) < 0x20 => s.push_str(&format!("\\u{:04x}", c as u32)),
+            c => s.push(c),
+        }
+    }
+    s.push('"');
+    s
+}
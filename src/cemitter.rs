@@ -2,25 +2,180 @@
 // The C emitter
 // Stephen Marz
 
+use crate::ast::{Expr, NumberType, Printable, Program, Statement};
 use crate::emit::Emitter;
-use std::fs::File;
-use std::io::prelude::*;
+use std::io::{self, Write};
 
 pub struct CEmitter {
-    full_path: String,
     header: String,
     code: String,
+    /// The Teeny file named in #line directives, if there are to be any
+    line_file: Option<String>,
+    /// Taken from the program
+    number_type: NumberType,
+}
+
+impl Default for CEmitter {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl CEmitter {
-    pub fn new(full_path: String) -> Self {
+    pub fn new() -> Self {
         Self {
-            full_path,
             header: String::new(),
             code: String::new(),
+            line_file: None,
+            number_type: NumberType::F32,
         }
     }
+
+    /// The C type of every value
+    fn c_type(&self) -> &'static str {
+        match self.number_type {
+            NumberType::F32 => "float",
+            NumberType::F64 => "double",
+        }
+    }
+
+    fn expression(&self, e: &Expr) -> String {
+        expression(e, self.number_type)
+    }
+
+    /// Put a #line directive before each statement, so that the C compiler
+    /// reports problems at the line of `file` they came from
+    pub fn line_directives(mut self, file: &str) -> Self {
+        self.line_file = Some(file.to_string());
+        self
+    }
+
+    fn statement(&mut self, stmt: &Statement) {
+        if let Some(file) = self.line_file.clone() {
+            self.emit_line(&format!("#line {} {}", stmt.span().line, string_literal(&file)));
+        }
+        match stmt {
+            Statement::Print { value: Printable::String(text), newline, .. } => {
+                // fputs, unlike printf, never treats the text as a format.
+                let mut text = text.clone();
+                if *newline {
+                    text.push('\n');
+                }
+                self.emit_line(&format!("fputs({}, stdout);", string_literal(&text)));
+            }
+            Statement::Print { value: Printable::Expr(e), newline, .. } => {
+                let nl = if *newline { "\\n" } else { "" };
+                let e = self.expression(e);
+                self.emit_line(&format!("printf(\"%.2f{}\", ({})({}));", nl, self.c_type(), e));
+            }
+            Statement::If { condition, body, .. } => {
+                self.emit_line(&format!("if ({}) {{", self.expression(condition)));
+                for s in body {
+                    self.statement(s);
+                }
+                self.emit_line("}");
+            }
+            Statement::While { condition, body, .. } => {
+                self.emit_line(&format!("while ({}) {{", self.expression(condition)));
+                for s in body {
+                    self.statement(s);
+                }
+                self.emit_line("}");
+            }
+            Statement::Label { name, .. } => {
+                self.emit_line(&format!("{}:", label(name)));
+            }
+            Statement::Goto { name, .. } => {
+                self.emit_line(&format!("goto {};", label(name)));
+            }
+            Statement::Let { name, value, .. } => {
+                self.emit_line(&format!("{} = {};", variable(name), self.expression(value)));
+            }
+            Statement::Input { name, .. } => {
+                let format = match self.number_type {
+                    NumberType::F32 => "%f",
+                    NumberType::F64 => "%lf",
+                };
+                self.emit_line(&format!("if (0 == scanf(\"{}\", &{})) {{", format, variable(name)));
+                self.emit_line(&format!("{} = 0;", variable(name)));
+                self.emit_line("scanf(\"%*s\");");
+                self.emit_line("}");
+            }
+        }
+    }
+}
+
+/// The C name of a variable. Every Teeny identifier gets a prefix, so none
+/// can clash with a C keyword, a libc function or main.
+fn variable(name: &str) -> String {
+    format!("v_{}", name)
 }
+
+/// The C name of a label. Labels get their own prefix so that a label and
+/// a variable with the same name stay apart, as they are in Teeny.
+fn label(name: &str) -> String {
+    format!("L_{}", name)
+}
+
+/// A C string literal. Anything outside printable ASCII is written as an
+/// octal escape, which unlike a hex escape can't run into the next
+/// character.
+fn string_literal(text: &str) -> String {
+    let mut s = String::from("\"");
+    let bytes = text.as_bytes();
+    for (i, &b) in bytes.iter().enumerate() {
+        match b {
+            b'"' => s.push_str("\\\""),
+            b'\\' => s.push_str("\\\\"),
+            b'\n' => s.push_str("\\n"),
+            b'\t' => s.push_str("\\t"),
+            // Keep ?? from starting a trigraph.
+            b'?' if i > 0 && bytes[i - 1] == b'?' => s.push_str("\\?"),
+            0x20..=0x7e => s.push(b as char),
+            _ => s.push_str(&format!("\\{:03o}", b)),
+        }
+    }
+    s.push('"');
+    s
+}
+
+/// Teeny numbers are floats or doubles, so literals always have a point
+/// or an exponent, and float ones an f suffix. Otherwise C would treat
+/// `1 / 2` as integer division.
+fn float_literal(text: &str, number: NumberType) -> String {
+    let value = number.parse(text).unwrap_or(0.0);
+    match number {
+        NumberType::F32 if value.is_infinite() => String::from("(1.0f / 0.0f)"),
+        NumberType::F32 => format!("{}f", number.text(value)),
+        NumberType::F64 if value.is_infinite() => String::from("(1.0 / 0.0)"),
+        NumberType::F64 => number.text(value),
+    }
+}
+
+/// Render an expression as C, adding parentheses only where needed.
+fn expression(e: &Expr, number: NumberType) -> String {
+    let expression = |e| expression(e, number);
+    match e {
+        Expr::Number(n, _) => float_literal(n, number),
+        Expr::Var(v, _) => variable(v),
+        Expr::Unary(op, inner, _) => match **inner {
+            Expr::Binary(..) | Expr::Unary(..) => format!("{}({})", op.symbol(), expression(inner)),
+            _ => format!("{}{}", op.symbol(), expression(inner)),
+        },
+        Expr::Binary(op, l, r, _) => {
+            let left = match **l {
+                Expr::Binary(lop, ..) if lop.precedence() < op.precedence() => format!("({})", expression(l)),
+                _ => expression(l),
+            };
+            let right = match **r {
+                Expr::Binary(rop, ..) if rop.precedence() <= op.precedence() => format!("({})", expression(r)),
+                _ => expression(r),
+            };
+            format!("{} {} {}", left, op.symbol(), right)
+        }
+    }
+}
+
 impl Emitter for CEmitter {
     fn emit(&mut self, code: &str) {
         self.code.push_str(code);
@@ -36,15 +191,25 @@ impl Emitter for CEmitter {
         self.header.push('\n');
     }
 
-    fn write_file(&self) {
-        if let Ok(mut f) = File::create(&self.full_path) {
-            // Use let _ to avoid checking the result.
-            let _ = f.write_all(self.header.as_bytes());
-            let _ = f.write_all(self.code.as_bytes());
-            let _ = f.sync_all();
+    fn write(&self, out: &mut dyn Write) -> io::Result<()> {
+        out.write_all(self.header.as_bytes())?;
+        out.write_all(self.code.as_bytes())
+    }
+
+    fn program(&mut self, program: &Program) {
+        self.header_line("#include <stdio.h>");
+        self.header_line("int main(void) {");
+        // Declare from the variable list rather than at each LET, since the
+        // optimizer may have removed the first assignment. They start at 0,
+        // since an INPUT at the end of the input leaves them alone.
+        self.number_type = program.number_type;
+        for v in program.variables.iter() {
+            let zero = float_literal("0", self.number_type);
+            self.header_line(&format!("{} {} = {};", self.c_type(), variable(v), zero));
         }
-        else {
-            abort!("Could not open file for writing: '{}'", self.full_path);
+        for stmt in program.statements.iter() {
+            self.statement(stmt);
         }
+        self.emit_line("return 0;\n}");
     }
-}
\ No newline at end of file
+}
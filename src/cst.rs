@@ -0,0 +1,396 @@
+// cst.rs
+// A lossless concrete syntax tree for tools such as a formatter. Every byte
+// of the source, whitespace and comments included, is in exactly one token
+// of the tree, so printing the tree gives back the source. Compiling goes
+// through the AST from parse.rs instead.
+
+use crate::ast::NumberType;
+use crate::diagnostic::Diagnostic;
+use crate::lex::Lexer;
+use crate::token::{Dialect, Span, Token, TokenType};
+use std::fmt;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum NodeKind {
+    Program,
+    /// The statements between THEN and ENDIF or REPEAT and ENDWHILE
+    Block,
+    PrintStmt,
+    IfStmt,
+    WhileStmt,
+    /// A LABEL with the doc comments before it
+    LabelStmt,
+    GotoStmt,
+    LetStmt,
+    InputStmt,
+    BinaryExpr,
+    UnaryExpr,
+    /// A number
+    Literal,
+    /// A variable
+    Name,
+}
+
+/// A token as it was written in the source
+#[derive(Clone, Debug)]
+pub struct CstToken {
+    pub kind: TokenType,
+    pub text: String,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug)]
+pub enum Element {
+    Node(Node),
+    Token(CstToken),
+}
+
+#[derive(Clone, Debug)]
+pub struct Node {
+    pub kind: NodeKind,
+    pub children: Vec<Element>,
+}
+
+impl Node {
+    /// Every token under this node, in source order
+    pub fn tokens(&self) -> Vec<&CstToken> {
+        let mut tokens = Vec::new();
+        self.collect_tokens(&mut tokens);
+        tokens
+    }
+
+    fn collect_tokens<'a>(&'a self, tokens: &mut Vec<&'a CstToken>) {
+        for child in self.children.iter() {
+            match child {
+                Element::Node(n) => n.collect_tokens(tokens),
+                Element::Token(t) => tokens.push(t),
+            }
+        }
+    }
+
+    /// The child nodes, skipping tokens
+    pub fn nodes(&self) -> impl Iterator<Item = &Node> {
+        self.children.iter().filter_map(|c| match c {
+            Element::Node(n) => Some(n),
+            Element::Token(_) => None,
+        })
+    }
+
+    /// The byte range the node covers
+    pub fn range(&self) -> (usize, usize) {
+        let tokens = self.tokens();
+        match (tokens.first(), tokens.last()) {
+            (Some(first), Some(last)) => (first.span.start, last.span.end),
+            _ => (0, 0),
+        }
+    }
+
+    /// An indented listing of the tree, for --emit=cst
+    pub fn dump(&self) -> String {
+        let mut out = String::new();
+        self.dump_into(&mut out, 0);
+        out
+    }
+
+    fn dump_into(&self, out: &mut String, depth: usize) {
+        let (start, end) = self.range();
+        out.push_str(&format!("{:indent$}{:?}@{}..{}\n", "", self.kind, start, end, indent = depth * 2));
+        for child in self.children.iter() {
+            match child {
+                Element::Node(n) => n.dump_into(out, depth + 1),
+                Element::Token(t) => out.push_str(&format!(
+                    "{:indent$}{:?}@{}..{} {:?}\n",
+                    "",
+                    t.kind,
+                    t.span.start,
+                    t.span.end,
+                    t.text,
+                    indent = (depth + 1) * 2
+                )),
+            }
+        }
+    }
+}
+
+/// Prints the source text the node was parsed from
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for t in self.tokens() {
+            f.write_str(&t.text)?;
+        }
+        Ok(())
+    }
+}
+
+fn is_trivia(kind: TokenType) -> bool {
+    kind == TokenType::Whitespace || kind == TokenType::Comment
+}
+
+/// Parse source into a concrete syntax tree
+pub fn parse(source: &str, dialect: Dialect) -> Result<Node, Diagnostic> {
+    // Formatting doesn't depend on the number type, so only reject what
+    // neither type can hold.
+    let tokens = Lexer::with_dialect(source, dialect)
+        .number_type(NumberType::F64)
+        .keep_trivia()
+        .collect::<Result<Vec<Token>, _>>()?
+        .into_iter()
+        .map(|t| {
+            // The lexer adds a newline if the last line has none; that one
+            // isn't in the source, so it gets no text and an empty span.
+            let mut span = t.span;
+            span.start = span.start.min(source.len());
+            span.end = span.end.min(source.len());
+            CstToken { kind: t.kind, text: source[span.start..span.end].to_string(), span }
+        })
+        .collect();
+    let mut parser = CstParser { tokens, pos: 0, stack: Vec::new() };
+    parser.program()
+}
+
+/// Builds the tree with a stack of unfinished nodes. Trivia goes into
+/// whichever node is open when it turns up, but never starts a node.
+struct CstParser {
+    tokens: Vec<CstToken>,
+    pos: usize,
+    stack: Vec<(NodeKind, Vec<Element>)>,
+}
+
+impl CstParser {
+    /// The type of the next token that isn't trivia
+    fn peek(&self) -> TokenType {
+        self.tokens[self.pos..]
+            .iter()
+            .map(|t| t.kind)
+            .find(|k| !is_trivia(*k))
+            .unwrap_or(TokenType::Eof)
+    }
+
+    /// Where the next token that isn't trivia is, for errors
+    fn location(&self) -> String {
+        match self.tokens[self.pos..].iter().find(|t| !is_trivia(t.kind)) {
+            Some(t) => format!("line {}, column {}", t.span.line, t.span.column),
+            None => String::from("end of file"),
+        }
+    }
+
+    fn push(&mut self, element: Element) {
+        self.stack.last_mut().unwrap().1.push(element);
+    }
+
+    /// Add any trivia that comes next to the open node
+    fn trivia(&mut self) {
+        while self.pos < self.tokens.len() && is_trivia(self.tokens[self.pos].kind) {
+            let t = self.tokens[self.pos].clone();
+            self.push(Element::Token(t));
+            self.pos += 1;
+        }
+    }
+
+    /// Add the next token to the open node
+    fn bump(&mut self) {
+        self.trivia();
+        if self.pos < self.tokens.len() {
+            let t = self.tokens[self.pos].clone();
+            self.push(Element::Token(t));
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, kind: TokenType) -> Result<(), Diagnostic> {
+        if self.peek() != kind {
+            abort!("Expected {:?}, got {:?} at {}", kind, self.peek(), self.location());
+        }
+        self.bump();
+        Ok(())
+    }
+
+    fn start(&mut self, kind: NodeKind) {
+        self.trivia();
+        self.stack.push((kind, Vec::new()));
+    }
+
+    fn finish(&mut self) {
+        let (kind, children) = self.stack.pop().unwrap();
+        self.push(Element::Node(Node { kind, children }));
+    }
+
+    /// A point to go back to when it turns out that what was just parsed
+    /// is the start of a bigger node
+    fn checkpoint(&mut self) -> usize {
+        self.trivia();
+        self.stack.last().unwrap().1.len()
+    }
+
+    /// Start a node that takes in everything since the checkpoint
+    fn start_at(&mut self, checkpoint: usize, kind: NodeKind) {
+        let children = self.stack.last_mut().unwrap().1.split_off(checkpoint);
+        self.stack.push((kind, children));
+    }
+
+    fn program(&mut self) -> Result<Node, Diagnostic> {
+        self.stack.push((NodeKind::Program, Vec::new()));
+        loop {
+            match self.peek() {
+                TokenType::Eof => break,
+                TokenType::Newline => self.bump(),
+                _ => self.statement()?,
+            }
+        }
+        self.trivia();
+        let (kind, children) = self.stack.pop().unwrap();
+        Ok(Node { kind, children })
+    }
+
+    /// Statements up to the given closing keyword, which is left alone
+    fn block(&mut self, end: TokenType) -> Result<(), Diagnostic> {
+        // Not start(), so that comments on the first lines belong to the
+        // block rather than to the statement that opens it.
+        self.stack.push((NodeKind::Block, Vec::new()));
+        while self.peek() != end {
+            match self.peek() {
+                TokenType::Eof => abort!("Expected {:?}, got Eof at {}", end, self.location()),
+                TokenType::Newline => self.bump(),
+                _ => self.statement()?,
+            }
+        }
+        self.finish();
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<(), Diagnostic> {
+        match self.peek() {
+            TokenType::Print | TokenType::PrintLn => {
+                self.start(NodeKind::PrintStmt);
+                self.bump();
+                if self.peek() == TokenType::String {
+                    self.bump();
+                }
+                else {
+                    self.expression()?;
+                }
+            }
+            TokenType::If => {
+                self.start(NodeKind::IfStmt);
+                self.bump();
+                self.comparison()?;
+                self.expect(TokenType::Then)?;
+                self.expect(TokenType::Newline)?;
+                self.block(TokenType::EndIf)?;
+                self.expect(TokenType::EndIf)?;
+            }
+            TokenType::While => {
+                self.start(NodeKind::WhileStmt);
+                self.bump();
+                self.comparison()?;
+                self.expect(TokenType::Repeat)?;
+                self.expect(TokenType::Newline)?;
+                self.block(TokenType::EndWhile)?;
+                self.expect(TokenType::EndWhile)?;
+            }
+            TokenType::Label | TokenType::DocComment => {
+                self.start(NodeKind::LabelStmt);
+                while self.peek() == TokenType::DocComment {
+                    self.bump();
+                    self.expect(TokenType::Newline)?;
+                    while self.peek() == TokenType::Newline {
+                        self.bump();
+                    }
+                }
+                self.expect(TokenType::Label)?;
+                self.expect(TokenType::Ident)?;
+            }
+            TokenType::Goto => {
+                self.start(NodeKind::GotoStmt);
+                self.bump();
+                self.expect(TokenType::Ident)?;
+            }
+            TokenType::Let => {
+                self.start(NodeKind::LetStmt);
+                self.bump();
+                self.expect(TokenType::Ident)?;
+                self.expect(TokenType::Eq)?;
+                self.expression()?;
+            }
+            TokenType::Input => {
+                self.start(NodeKind::InputStmt);
+                self.bump();
+                self.expect(TokenType::Ident)?;
+            }
+            other => abort!("Invalid statement ({:?}) at {}", other, self.location()),
+        }
+        self.expect(TokenType::Newline)?;
+        self.finish();
+        Ok(())
+    }
+
+    /// Parse operands separated by any of `ops`, building left-nested
+    /// binary nodes
+    fn binary(&mut self, ops: &[TokenType], operand: fn(&mut Self) -> Result<(), Diagnostic>) -> Result<(), Diagnostic> {
+        let checkpoint = self.checkpoint();
+        operand(self)?;
+        while ops.contains(&self.peek()) {
+            self.start_at(checkpoint, NodeKind::BinaryExpr);
+            self.bump();
+            operand(self)?;
+            self.finish();
+        }
+        Ok(())
+    }
+
+    fn comparison(&mut self) -> Result<(), Diagnostic> {
+        const COMPARISONS: [TokenType; 6] = [
+            TokenType::EqEq,
+            TokenType::NotEq,
+            TokenType::Lt,
+            TokenType::LtEq,
+            TokenType::Gt,
+            TokenType::GtEq,
+        ];
+        let checkpoint = self.checkpoint();
+        self.expression()?;
+        if !COMPARISONS.contains(&self.peek()) {
+            abort!("Expected comparison operator at {}", self.location());
+        }
+        while COMPARISONS.contains(&self.peek()) {
+            self.start_at(checkpoint, NodeKind::BinaryExpr);
+            self.bump();
+            self.expression()?;
+            self.finish();
+        }
+        Ok(())
+    }
+
+    fn expression(&mut self) -> Result<(), Diagnostic> {
+        self.binary(&[TokenType::Plus, TokenType::Minus], Self::term)
+    }
+
+    fn term(&mut self) -> Result<(), Diagnostic> {
+        self.binary(&[TokenType::Asterisk, TokenType::Slash], Self::unary)
+    }
+
+    fn unary(&mut self) -> Result<(), Diagnostic> {
+        if matches!(self.peek(), TokenType::Plus | TokenType::Minus) {
+            self.start(NodeKind::UnaryExpr);
+            self.bump();
+            self.primary()?;
+            self.finish();
+            Ok(())
+        }
+        else {
+            self.primary()
+        }
+    }
+
+    fn primary(&mut self) -> Result<(), Diagnostic> {
+        let kind = match self.peek() {
+            TokenType::Number => NodeKind::Literal,
+            TokenType::Ident => NodeKind::Name,
+            other => abort!("Unexpected token {:?} at {}", other, self.location()),
+        };
+        self.start(kind);
+        self.bump();
+        self.finish();
+        Ok(())
+    }
+}
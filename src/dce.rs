@@ -0,0 +1,156 @@
+// dce.rs
+// Dead code elimination. Removes statements that control can never reach
+// and labels that no GOTO jumps to, warning about each. What is reachable
+// comes from the control flow graph of the IR.
+
+use crate::ast::{Expr, Printable, Program, Statement};
+use crate::diagnostic::Diagnostic;
+use crate::ir::Ir;
+use crate::lint::{Lint, Lints};
+use crate::token::Span;
+use std::collections::BTreeSet;
+
+/// The labels whose blocks can be reached, and those of them that a
+/// reachable GOTO jumps to
+struct Labels {
+    reachable: BTreeSet<String>,
+    used: BTreeSet<String>,
+}
+
+/// Remove unreachable statements and unused labels from the program
+pub fn eliminate_dead_code(program: &mut Program, lints: &Lints) -> Result<(), Diagnostic> {
+    let ir = Ir::new(program);
+    let reachable = ir.reachable();
+    let labels = Labels {
+        reachable: ir.blocks.iter().zip(reachable).filter(|(_, r)| *r).filter_map(|(b, _)| b.label.clone()).collect(),
+        used: ir.goto_targets(),
+    };
+    let statements = std::mem::take(&mut program.statements);
+    program.statements = prune(statements, true, &labels, &mut None, lints)?.0;
+
+    // Forget variables that only appeared in removed code.
+    let mut used = BTreeSet::new();
+    find_variables(&program.statements, &mut used);
+    program.variables.retain(|v| used.contains(v));
+    Ok(())
+}
+
+fn find_variables(statements: &[Statement], used: &mut BTreeSet<String>) {
+    for stmt in statements {
+        match stmt {
+            Statement::Let { name, value, .. } => {
+                used.insert(name.clone());
+                expr_variables(value, used);
+            }
+            Statement::Input { name, .. } => {
+                used.insert(name.clone());
+            }
+            Statement::Print { value: Printable::Expr(e), .. } => expr_variables(e, used),
+            Statement::If { condition, body, .. } | Statement::While { condition, body, .. } => {
+                expr_variables(condition, used);
+                find_variables(body, used);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn expr_variables(e: &Expr, used: &mut BTreeSet<String>) {
+    match e {
+        Expr::Var(v, _) => {
+            used.insert(v.clone());
+        }
+        Expr::Unary(_, inner, _) => expr_variables(inner, used),
+        Expr::Binary(_, l, r, _) => {
+            expr_variables(l, used);
+            expr_variables(r, used);
+        }
+        Expr::Number(_, _) => {}
+    }
+}
+
+/// Drop dead statements. `pending` is the GOTO whose dead code hasn't been
+/// reported yet. Returns the remaining statements and whether control can
+/// fall off the end.
+fn prune(statements: Vec<Statement>, mut reachable: bool, labels: &Labels, pending: &mut Option<String>, lints: &Lints) -> Result<(Vec<Statement>, bool), Diagnostic> {
+    let mut out = Vec::with_capacity(statements.len());
+    for stmt in statements {
+        match stmt {
+            Statement::Label { name, doc, span } => {
+                if labels.used.contains(&name) {
+                    reachable = true;
+                    *pending = None;
+                    out.push(Statement::Label { name, doc, span });
+                }
+                // Only jumped to from dead code, so dead code itself.
+                else if !labels.reachable.contains(&name) {
+                    report(pending, span, lints)?;
+                }
+                else {
+                    lints.report(Lint::UnusedLabel, span, &format!("Label '{}' is never used.", name))?;
+                }
+            }
+            Statement::If { condition, body, span } => {
+                let entered = reachable;
+                let (body, end) = prune(body, reachable, labels, pending, lints)?;
+                reachable |= end;
+                // Anything left in the body of an unreachable IF is there
+                // because of a reachable label, so the IF has to stay.
+                if entered || !body.is_empty() {
+                    out.push(Statement::If { condition, body, span });
+                }
+                else {
+                    report(pending, span, lints)?;
+                }
+            }
+            Statement::While { condition, body, span } => {
+                let entered = reachable;
+                // A GOTO into the body makes the top of the body reachable
+                // too, through the loop condition.
+                let start = reachable || reaches_end_from_label(&body, labels);
+                let (body, end) = prune(body, start, labels, pending, lints)?;
+                reachable |= end;
+                if entered || !body.is_empty() {
+                    out.push(Statement::While { condition, body, span });
+                }
+                else {
+                    report(pending, span, lints)?;
+                }
+            }
+            _ if !reachable => report(pending, stmt.span(), lints)?,
+            Statement::Goto { name, span } => {
+                *pending = Some(name.clone());
+                reachable = false;
+                out.push(Statement::Goto { name, span });
+            }
+            other => out.push(other),
+        }
+    }
+    Ok((out, reachable))
+}
+
+/// Whether control can get to the end of `statements` from a GOTO to one
+/// of their labels, without coming in at the start
+fn reaches_end_from_label(statements: &[Statement], labels: &Labels) -> bool {
+    let mut reachable = false;
+    for stmt in statements {
+        match stmt {
+            Statement::Label { name, .. } if labels.used.contains(name) => reachable = true,
+            Statement::If { body, .. } | Statement::While { body, .. } => {
+                reachable |= reaches_end_from_label(body, labels);
+            }
+            Statement::Goto { .. } => reachable = false,
+            _ => {}
+        }
+    }
+    reachable
+}
+
+/// Report the dead code after the pending GOTO at the first statement
+/// removed, `span`
+fn report(pending: &mut Option<String>, span: Span, lints: &Lints) -> Result<(), Diagnostic> {
+    match pending.take() {
+        Some(name) => lints.report(Lint::UnreachableCode, span, &format!("Unreachable code after GOTO {} removed.", name)),
+        None => Ok(()),
+    }
+}
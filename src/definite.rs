@@ -0,0 +1,168 @@
+// definite.rs
+// Definite assignment analysis. Every read of a variable must come after an
+// assignment on every path through the control flow graph, otherwise the C
+// target would read an uninitialised variable.
+
+use crate::ast::Program;
+use crate::diagnostic::Diagnostic;
+use crate::ir::{Instr, Ir, Operand, Terminator};
+use crate::token::Span;
+use std::collections::{BTreeSet, VecDeque};
+
+/// The variable an instruction assigns, if any
+fn assigned(instr: &Instr) -> Option<&str> {
+    match instr {
+        Instr::Copy { dest: Operand::Var(v), .. }
+        | Instr::Neg { dest: Operand::Var(v), .. }
+        | Instr::Binary { dest: Operand::Var(v), .. }
+        | Instr::Input(v) => Some(v),
+        _ => None,
+    }
+}
+
+/// The variables an instruction reads
+fn read(instr: &Instr) -> Vec<&str> {
+    let operands = match instr {
+        Instr::Copy { src, .. } | Instr::Neg { src, .. } => vec![src],
+        Instr::Binary { left, right, .. } => vec![left, right],
+        Instr::Print { value, .. } => vec![value],
+        Instr::PrintStr { .. } | Instr::Input(_) => Vec::new(),
+    };
+    operands
+        .into_iter()
+        .filter_map(|o| match o {
+            Operand::Var(v) => Some(v.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Fail if any variable may be read before it has been assigned
+pub fn check_definite_assignment(program: &Program) -> Result<(), Diagnostic> {
+    let ir = Ir::new(program);
+    let n = ir.blocks.len();
+    let all: BTreeSet<&str> = program.variables.iter().map(|v| v.as_str()).collect();
+
+    // The variables assigned on every path into each block. Blocks that
+    // can't be reached start out with everything so they don't get in the
+    // way.
+    let preds = ir.predecessors();
+    let mut assigned_in = vec![all.clone(); n];
+    assigned_in[0] = BTreeSet::new();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for b in 1..n {
+            let mut set: Option<BTreeSet<&str>> = None;
+            for &p in preds[b].iter() {
+                let mut out = assigned_in[p].clone();
+                out.extend(ir.blocks[p].instrs.iter().filter_map(assigned));
+                set = Some(match set {
+                    None => out,
+                    Some(s) => s.intersection(&out).copied().collect(),
+                });
+            }
+            let set = set.unwrap_or_else(|| all.clone());
+            if set != assigned_in[b] {
+                assigned_in[b] = set;
+                changed = true;
+            }
+        }
+    }
+
+    for (b, block) in ir.blocks.iter().enumerate() {
+        let mut assigned_now = assigned_in[b].clone();
+        for (i, instr) in block.instrs.iter().enumerate() {
+            for v in read(instr) {
+                if !assigned_now.contains(v) {
+                    return Err(report(&ir, b, v, block.reads[&(i, v.to_string())]));
+                }
+            }
+            if let Some(v) = assigned(instr) {
+                assigned_now.insert(v);
+            }
+        }
+        if let Terminator::Branch { condition: Operand::Var(v), .. } = &block.terminator {
+            if !assigned_now.contains(v.as_str()) {
+                return Err(report(&ir, b, v, block.reads[&(block.instrs.len(), v.clone())]));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The error for a read of `var` at `span` in `block` that may come before
+/// it is assigned
+fn report(ir: &Ir, block: usize, var: &str, span: Span) -> Diagnostic {
+    let at = format!("at line {}, column {}", span.line, span.column);
+    let assigns = |b: usize| ir.blocks[b].instrs.iter().any(|i| assigned(i) == Some(var));
+    if !ir.blocks.iter().enumerate().any(|(b, _)| assigns(b)) {
+        return Diagnostic::error(format!("Variable '{}' is used but never assigned {}", var, at));
+    }
+
+    // Find the shortest path from the start to the use that never assigns
+    // the variable.
+    let mut came_from = vec![None; ir.blocks.len()];
+    let mut queue = VecDeque::from([0]);
+    while let Some(b) = queue.pop_front() {
+        if b == block {
+            break;
+        }
+        for s in ir.blocks[b].terminator.successors() {
+            if s != 0 && came_from[s].is_none() && (s == block || !assigns(s)) {
+                came_from[s] = Some(b);
+                queue.push_back(s);
+            }
+        }
+    }
+    let mut path = vec![block];
+    while let Some(p) = came_from[*path.last().unwrap()] {
+        path.push(p);
+    }
+    path.reverse();
+
+    let mut steps = Vec::new();
+    for edge in path.windows(2) {
+        let (from, to) = (edge[0], edge[1]);
+        if let Terminator::Branch { condition, then, otherwise } = &ir.blocks[from].terminator {
+            if then != otherwise {
+                let taken = if to == *then { "true" } else { "false" };
+                steps.push(format!("'{}' is {}", describe(ir, from, condition), taken));
+            }
+        }
+        if let Some(label) = &ir.blocks[to].label {
+            steps.push(format!("LABEL {}", label));
+        }
+    }
+    if steps.is_empty() {
+        return Diagnostic::error(format!("Variable '{}' is used before assignment {}", var, at));
+    }
+    Diagnostic::error(format!(
+        "Variable '{}' is possibly used before assignment {}, on the path: {}",
+        var,
+        at,
+        steps.join(", then ")
+    ))
+}
+
+/// Rebuild the source text of an operand from the instructions in its block
+fn describe(ir: &Ir, block: usize, o: &Operand) -> String {
+    match o {
+        Operand::Const(c) => format!("{}", c),
+        Operand::Var(v) => v.clone(),
+        Operand::Temp(t) => {
+            for instr in ir.blocks[block].instrs.iter() {
+                match instr {
+                    Instr::Binary { dest: Operand::Temp(d), op, left, right } if d == t => {
+                        return format!("{} {} {}", describe(ir, block, left), op.symbol(), describe(ir, block, right));
+                    }
+                    Instr::Neg { dest: Operand::Temp(d), src } if d == t => {
+                        return format!("-{}", describe(ir, block, src));
+                    }
+                    _ => {}
+                }
+            }
+            o.to_string()
+        }
+    }
+}
@@ -0,0 +1,70 @@
+// diagnostic.rs
+// Errors and warnings. The compiler hands these back instead of printing
+// them, so that a program using the library decides where they go.
+
+use crate::token::Span;
+use std::fmt;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    /// The source the problem is in, when it is about one place
+    pub span: Option<Span>,
+}
+
+impl Diagnostic {
+    pub fn error(message: String) -> Self {
+        Self { severity: Severity::Error, message, span: None }
+    }
+
+    pub fn warning(message: String) -> Self {
+        Self { severity: Severity::Warning, message, span: None }
+    }
+
+    /// The same diagnostic, pointing at `span`
+    pub fn at(self, span: Span) -> Self {
+        Self { span: Some(span), ..self }
+    }
+}
+
+/// Prints the way the command line reports it
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.severity {
+            Severity::Error => write!(f, "Compiler error")?,
+            Severity::Warning => write!(f, "Compiler warning")?,
+        }
+        if let Some(span) = self.span {
+            write!(f, " at line {}, column {}", span.line, span.column)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+/// Why a compile failed: the error that stopped it, and the warnings
+/// reported before that
+#[derive(Clone, Debug)]
+pub struct Diagnostics {
+    pub warnings: Vec<Diagnostic>,
+    pub error: Diagnostic,
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for w in self.warnings.iter() {
+            writeln!(f, "{}", w)?;
+        }
+        write!(f, "{}", self.error)
+    }
+}
+
+impl std::error::Error for Diagnostics {}
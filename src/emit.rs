@@ -1,10 +1,79 @@
 // emit.rs
 // Emitter
 
+use crate::ast::Program;
+use crate::cemitter::CEmitter;
+use crate::jsemitter::JsEmitter;
+use crate::llvmemitter::LlvmEmitter;
+use crate::opt::Passes;
+use crate::pyemitter::PyEmitter;
+use crate::qbeemitter::QbeEmitter;
+use crate::rustemitter::RustEmitter;
+use crate::watemitter::WatEmitter;
+use std::io::{self, Write};
+
 pub trait Emitter {
     fn emit(&mut self, code: &str);
     fn emit_line(&mut self, code: &str);
     fn header_line(&mut self, code: &str);
-    fn write_file(&self);
+    /// Write the generated code to `out`, which can be a file, stdout or
+    /// a `Vec<u8>`
+    fn write(&self, out: &mut dyn Write) -> io::Result<()>;
+    /// Generate the code for an entire program
+    fn program(&mut self, program: &Program);
+}
+
+/// The languages code can be generated in
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Target {
+    C,
+    Llvm,
+    Wasm,
+    Qbe,
+    Rust,
+    Js,
+    Python,
 }
 
+impl Target {
+    /// The name used with --target
+    pub fn by_name(name: &str) -> Option<Target> {
+        match name {
+            "c" => Some(Target::C),
+            "llvm" => Some(Target::Llvm),
+            "wasm" => Some(Target::Wasm),
+            "qbe" => Some(Target::Qbe),
+            "rust" => Some(Target::Rust),
+            "js" => Some(Target::Js),
+            "python" => Some(Target::Python),
+            _ => None,
+        }
+    }
+
+    /// Where the code goes when no -o is given
+    pub fn default_path(&self) -> &'static str {
+        match self {
+            Target::C => "out.c",
+            Target::Llvm => "out.ll",
+            Target::Wasm => "out.wat",
+            Target::Qbe => "out.ssa",
+            Target::Rust => "out.rs",
+            Target::Js => "out.js",
+            Target::Python => "out.py",
+        }
+    }
+
+    /// An emitter for this target. C is generated straight from the AST,
+    /// so it ignores the IR passes.
+    pub fn emitter(&self, passes: Passes) -> Box<dyn Emitter> {
+        match self {
+            Target::C => Box::new(CEmitter::new()),
+            Target::Llvm => Box::new(LlvmEmitter::new(passes)),
+            Target::Wasm => Box::new(WatEmitter::new(passes)),
+            Target::Qbe => Box::new(QbeEmitter::new(passes)),
+            Target::Rust => Box::new(RustEmitter::new(passes)),
+            Target::Js => Box::new(JsEmitter::new(passes)),
+            Target::Python => Box::new(PyEmitter::new(passes)),
+        }
+    }
+}
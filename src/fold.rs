@@ -0,0 +1,126 @@
+// fold.rs
+// Constant folding and algebraic simplification on the AST, run at -O1.
+// Arithmetic is rounded to the program's number type so the results are
+// the ones the program would have computed at run time.
+
+use crate::ast::{BinOp, Expr, NumberType, Printable, Program, Statement, UnaryOp};
+use crate::token::Span;
+
+/// Fold every expression in the program and drop IFs with a constant
+/// condition.
+pub fn fold_program(program: &mut Program) {
+    let statements = std::mem::take(&mut program.statements);
+    program.statements = fold_statements(statements, program.number_type);
+}
+
+fn fold_statements(statements: Vec<Statement>, number: NumberType) -> Vec<Statement> {
+    let mut out = Vec::with_capacity(statements.len());
+    for stmt in statements {
+        match stmt {
+            Statement::If { condition, body, span } => {
+                let condition = fold_expr(condition, number);
+                let body = fold_statements(body, number);
+                match constant(&condition, number) {
+                    Some(c) if c != 0.0 => out.extend(body),
+                    // A GOTO may still jump into the body through a label.
+                    Some(_) if !contains_label(&body) => {}
+                    _ => out.push(Statement::If { condition, body, span }),
+                }
+            }
+            Statement::While { condition, body, span } => out.push(Statement::While {
+                condition: fold_expr(condition, number),
+                body: fold_statements(body, number),
+                span,
+            }),
+            Statement::Let { name, value, span } => {
+                out.push(Statement::Let { name, value: fold_expr(value, number), span })
+            }
+            Statement::Print { value: Printable::Expr(e), newline, span } => out.push(Statement::Print {
+                value: Printable::Expr(fold_expr(e, number)),
+                newline,
+                span,
+            }),
+            other => out.push(other),
+        }
+    }
+    out
+}
+
+fn contains_label(statements: &[Statement]) -> bool {
+    statements.iter().any(|s| match s {
+        Statement::Label { .. } => true,
+        Statement::If { body, .. } | Statement::While { body, .. } => contains_label(body),
+        _ => false,
+    })
+}
+
+/// The value of a literal, or of a negated literal
+fn constant(e: &Expr, number: NumberType) -> Option<f64> {
+    match e {
+        Expr::Number(n, _) => number.parse(n),
+        Expr::Unary(UnaryOp::Minus, inner, _) => match **inner {
+            Expr::Number(ref n, _) => number.parse(n).map(|v| -v),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// A literal for a folded value, in place of the expression at `span`.
+/// The lexer never produces negative numbers, so neither do we.
+fn literal(value: f64, number: NumberType, span: Span) -> Expr {
+    if value.is_sign_negative() {
+        Expr::Unary(UnaryOp::Minus, Box::new(Expr::Number(number.text(-value), span)), span)
+    }
+    else {
+        Expr::Number(number.text(value), span)
+    }
+}
+
+/// The result of a binary operation on two constants
+pub fn evaluate(op: BinOp, a: f64, b: f64, number: NumberType) -> f64 {
+    let truth = |t: bool| if t { 1.0 } else { 0.0 };
+    match op {
+        BinOp::Add => number.round(a + b),
+        BinOp::Sub => number.round(a - b),
+        BinOp::Mul => number.round(a * b),
+        BinOp::Div => number.round(a / b),
+        BinOp::EqEq => truth(a == b),
+        BinOp::NotEq => truth(a != b),
+        BinOp::Lt => truth(a < b),
+        BinOp::LtEq => truth(a <= b),
+        BinOp::Gt => truth(a > b),
+        BinOp::GtEq => truth(a >= b),
+    }
+}
+
+fn fold_expr(e: Expr, number: NumberType) -> Expr {
+    match e {
+        Expr::Unary(UnaryOp::Plus, inner, _) => fold_expr(*inner, number),
+        Expr::Unary(UnaryOp::Minus, inner, span) => match fold_expr(*inner, number) {
+            Expr::Unary(UnaryOp::Minus, x, _) => *x,
+            x => Expr::Unary(UnaryOp::Minus, Box::new(x), span),
+        },
+        Expr::Binary(op, l, r, span) => {
+            let l = fold_expr(*l, number);
+            let r = fold_expr(*r, number);
+            match (constant(&l, number), constant(&r, number)) {
+                (Some(a), Some(b)) => {
+                    let value = evaluate(op, a, b, number);
+                    // NaN's sign depends on the machine, so leave it to run time.
+                    if !value.is_nan() {
+                        return literal(value, number, span);
+                    }
+                }
+                // x - 0 is exact. x + 0 is not: -0 + 0 is 0. Neither is x - x, which is
+                // NaN for an infinite x.
+                (_, Some(b)) if b == 0.0 && op == BinOp::Sub => return l,
+                (_, Some(b)) if b == 1.0 && matches!(op, BinOp::Mul | BinOp::Div) => return l,
+                (Some(a), _) if a == 1.0 && op == BinOp::Mul => return r,
+                _ => {}
+            }
+            Expr::Binary(op, Box::new(l), Box::new(r), span)
+        }
+        other => other,
+    }
+}
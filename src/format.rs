@@ -0,0 +1,116 @@
+// format.rs
+// The source formatter behind `ttrust fmt`. Works from the concrete syntax
+// tree so comments survive. Statements are indented by four spaces per
+// block, keywords are upper case, tokens are separated by single spaces and
+// runs of blank lines become one.
+
+use crate::cst::{self, CstToken, Element, Node, NodeKind};
+use crate::diagnostic::Diagnostic;
+use crate::token::{Dialect, Token, TokenType};
+
+const INDENT: usize = 4;
+
+/// Format a Teeny program
+pub fn format(source: &str, dialect: Dialect) -> Result<String, Diagnostic> {
+    let tree = cst::parse(source, dialect)?;
+    let mut f = Formatter { out: String::new(), line: Vec::new(), glue: false };
+    f.level(&tree, 0);
+    Ok(f.out)
+}
+
+struct Formatter {
+    out: String,
+    /// The pieces of the line being built, to be joined with spaces
+    line: Vec<String>,
+    /// Whether the next piece joins onto the last without a space
+    glue: bool,
+}
+
+impl Formatter {
+    fn push(&mut self, text: &str) {
+        match self.line.last_mut() {
+            Some(last) if self.glue => last.push_str(text),
+            _ => self.line.push(text.to_string()),
+        }
+        self.glue = false;
+    }
+
+    /// Write out the line being built, if there is one
+    fn end_line(&mut self, depth: usize) {
+        if !self.line.is_empty() {
+            let text = self.line.join(" ");
+            self.out.push_str(&format!("{:indent$}{}\n", "", text, indent = depth * INDENT));
+            self.line.clear();
+        }
+        self.glue = false;
+    }
+
+    /// The statements, comments and blank lines of the program or a block
+    fn level(&mut self, node: &Node, depth: usize) {
+        let mut wrote = false;
+        let mut blank = false;
+        for child in node.children.iter() {
+            match child {
+                Element::Token(t) => match t.kind {
+                    TokenType::Newline if self.line.is_empty() => blank = true,
+                    TokenType::Newline => {
+                        self.blank_line(wrote, blank);
+                        self.end_line(depth);
+                        wrote = true;
+                        blank = false;
+                    }
+                    TokenType::Comment => self.push(t.text.trim_end()),
+                    _ => {}
+                },
+                // Comments earlier on the line stay in front of the
+                // statement.
+                Element::Node(n) => {
+                    self.blank_line(wrote, blank);
+                    self.statement(n, depth);
+                    wrote = true;
+                    blank = false;
+                }
+            }
+        }
+        // A comment at the very end of the file has no newline after it.
+        self.end_line(depth);
+    }
+
+    /// Keep one blank line where there were any, except at the start of
+    /// a block
+    fn blank_line(&mut self, wrote: bool, blank: bool) {
+        if wrote && blank {
+            self.out.push('\n');
+        }
+    }
+
+    fn statement(&mut self, node: &Node, depth: usize) {
+        for child in node.children.iter() {
+            match child {
+                Element::Node(n) if n.kind == NodeKind::Block => {
+                    self.end_line(depth);
+                    self.level(n, depth + 1);
+                }
+                Element::Node(n) => self.statement(n, depth),
+                Element::Token(t) => {
+                    self.token(t, depth);
+                    if node.kind == NodeKind::UnaryExpr && matches!(t.kind, TokenType::Plus | TokenType::Minus) {
+                        self.glue = true;
+                    }
+                }
+            }
+        }
+    }
+
+    fn token(&mut self, t: &CstToken, depth: usize) {
+        match t.kind {
+            TokenType::Whitespace => {}
+            TokenType::Newline => self.end_line(depth),
+            TokenType::Comment | TokenType::DocComment => self.push(t.text.trim_end()),
+            kind if Token::check_if_keyword(&t.text.to_ascii_uppercase()) == kind => {
+                self.push(&t.text.to_ascii_uppercase());
+            }
+            _ => self.push(&t.text),
+        }
+    }
+}
@@ -0,0 +1,501 @@
+// ir.rs
+// Three-address intermediate representation. IF, WHILE, LABEL and GOTO are
+// lowered once into basic blocks with explicit terminators, and every
+// expression is broken down into single operations on operands.
+
+use crate::ast::{BinOp, Expr, NumberType, Printable, Program, Statement, UnaryOp};
+use crate::token::Span;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// Something an instruction reads or writes
+#[derive(Clone, Debug, PartialEq)]
+pub enum Operand {
+    /// Always a value of the program's number type
+    Const(f64),
+    /// A Teeny variable. Variables the compiler makes up start with a digit
+    /// so they can't clash with one.
+    Var(String),
+    /// A compiler temporary. Each one is assigned exactly once.
+    Temp(usize),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Instr {
+    Copy { dest: Operand, src: Operand },
+    Neg { dest: Operand, src: Operand },
+    /// Comparisons store 1 or 0
+    Binary { dest: Operand, op: BinOp, left: Operand, right: Operand },
+    PrintStr { text: String, newline: bool },
+    Print { value: Operand, newline: bool },
+    /// scanf("%f") into a variable, leaving it alone at end of input
+    Input(String),
+}
+
+/// How control leaves a basic block
+#[derive(Clone, Debug, PartialEq)]
+pub enum Terminator {
+    Jump(usize),
+    /// Go to `then` when the condition is non-zero
+    Branch { condition: Operand, then: usize, otherwise: usize },
+    Return,
+}
+
+/// An SSA phi function. Only present between SSA construction and
+/// destruction, so backends never see one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Phi {
+    pub dest: usize,
+    /// The value coming in from each predecessor block
+    pub args: Vec<(usize, Operand)>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block {
+    /// The Teeny label that starts this block, if any
+    pub label: Option<String>,
+    pub phis: Vec<Phi>,
+    pub instrs: Vec<Instr>,
+    pub terminator: Terminator,
+    /// Where each variable read by an instruction is in the source, keyed
+    /// by the instruction's index, with instrs.len() for the terminator.
+    /// Only for IR fresh from Ir::new.
+    pub reads: BTreeMap<(usize, String), Span>,
+    /// The span of the LET each instruction storing a variable lowers,
+    /// keyed the same way. Only for IR fresh from Ir::new.
+    pub lets: BTreeMap<usize, Span>,
+}
+
+/// A lowered program. Execution starts at block 0.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Ir {
+    pub blocks: Vec<Block>,
+    pub variables: Vec<String>,
+    pub num_temps: usize,
+    pub number_type: NumberType,
+}
+
+impl Terminator {
+    /// The blocks control can go to next
+    pub fn successors(&self) -> Vec<usize> {
+        match self {
+            Terminator::Jump(t) => vec![*t],
+            Terminator::Branch { then, otherwise, .. } => vec![*then, *otherwise],
+            Terminator::Return => Vec::new(),
+        }
+    }
+}
+
+struct Builder {
+    blocks: Vec<Block>,
+    labels: BTreeMap<String, usize>,
+    cur: usize,
+    /// Blocks in the order they were entered, which is source order
+    order: Vec<usize>,
+    num_temps: usize,
+    number_type: NumberType,
+    /// Variables lowered into operands but not yet read by an instruction
+    pending: Vec<(String, Span)>,
+}
+
+impl Builder {
+    fn new_block(&mut self) -> usize {
+        self.blocks.push(Block {
+            label: None,
+            phis: Vec::new(),
+            instrs: Vec::new(),
+            terminator: Terminator::Return,
+            reads: BTreeMap::new(),
+            lets: BTreeMap::new(),
+        });
+        self.blocks.len() - 1
+    }
+
+    /// Close the current block and continue in `next`
+    fn finish(&mut self, terminator: Terminator, next: usize) {
+        self.blocks[self.cur].terminator = terminator;
+        self.cur = next;
+        self.order.push(next);
+    }
+
+    fn push(&mut self, instr: Instr) {
+        let operands = match &instr {
+            Instr::Copy { src, .. } | Instr::Neg { src, .. } => vec![src],
+            Instr::Binary { left, right, .. } => vec![left, right],
+            Instr::Print { value, .. } => vec![value],
+            Instr::PrintStr { .. } | Instr::Input(_) => Vec::new(),
+        };
+        // Operands were lowered left to right, so match them up from the end.
+        for o in operands.into_iter().rev() {
+            self.read(o);
+        }
+        self.blocks[self.cur].instrs.push(instr);
+    }
+
+    /// Record where a variable read by the next instruction or terminator
+    /// of the current block came from
+    fn read(&mut self, o: &Operand) {
+        if let Operand::Var(v) = o {
+            if let Some(n) = self.pending.iter().rposition(|(name, _)| name == v) {
+                let (name, span) = self.pending.remove(n);
+                let block = &mut self.blocks[self.cur];
+                block.reads.insert((block.instrs.len(), name), span);
+            }
+        }
+    }
+
+    fn temp(&mut self) -> Operand {
+        self.num_temps += 1;
+        Operand::Temp(self.num_temps)
+    }
+
+    /// Give every label its own block up front so GOTOs can jump forward.
+    fn declare_labels(&mut self, statements: &[Statement]) {
+        for stmt in statements {
+            match stmt {
+                Statement::Label { name, .. } => {
+                    let b = self.new_block();
+                    self.blocks[b].label = Some(name.clone());
+                    self.labels.insert(name.clone(), b);
+                }
+                Statement::If { body, .. } | Statement::While { body, .. } => self.declare_labels(body),
+                _ => {}
+            }
+        }
+    }
+
+    /// Lower an expression into an operand, using temporaries as needed
+    fn operand(&mut self, e: &Expr) -> Operand {
+        match e {
+            Expr::Number(n, _) => Operand::Const(self.number_type.parse(n).unwrap_or(0.0)),
+            Expr::Var(v, span) => {
+                self.pending.push((v.clone(), *span));
+                Operand::Var(v.clone())
+            }
+            Expr::Unary(UnaryOp::Plus, inner, _) => self.operand(inner),
+            Expr::Unary(UnaryOp::Minus, inner, _) if matches!(**inner, Expr::Number(_, _)) => match self.operand(inner) {
+                Operand::Const(c) => Operand::Const(-c),
+                _ => unreachable!(),
+            },
+            _ => {
+                let t = self.temp();
+                self.assign(t.clone(), e);
+                t
+            }
+        }
+    }
+
+    /// Lower an expression, storing the result in `dest`
+    fn assign(&mut self, dest: Operand, e: &Expr) {
+        match e {
+            Expr::Unary(UnaryOp::Plus, inner, _) => self.assign(dest, inner),
+            Expr::Unary(UnaryOp::Minus, inner, _) => {
+                let src = self.operand(inner);
+                self.push(Instr::Neg { dest, src });
+            }
+            Expr::Binary(op, l, r, _) => {
+                let left = self.operand(l);
+                let right = self.operand(r);
+                self.push(Instr::Binary { dest, op: *op, left, right });
+            }
+            _ => {
+                let src = self.operand(e);
+                self.push(Instr::Copy { dest, src });
+            }
+        }
+    }
+
+    fn branch(&mut self, condition: &Expr, then: usize, otherwise: usize) {
+        let condition = self.operand(condition);
+        self.read(&condition);
+        self.finish(Terminator::Branch { condition, then, otherwise }, then);
+    }
+
+    fn statements(&mut self, statements: &[Statement]) {
+        for stmt in statements {
+            match stmt {
+                Statement::Print { value: Printable::String(text), newline, .. } => {
+                    self.push(Instr::PrintStr { text: text.clone(), newline: *newline });
+                }
+                Statement::Print { value: Printable::Expr(e), newline, .. } => {
+                    let value = self.operand(e);
+                    self.push(Instr::Print { value, newline: *newline });
+                }
+                Statement::Let { name, value, span } => {
+                    self.assign(Operand::Var(name.clone()), value);
+                    // The store is the last instruction of the lowered LET.
+                    let block = &mut self.blocks[self.cur];
+                    block.lets.insert(block.instrs.len() - 1, *span);
+                }
+                Statement::Input { name, .. } => self.push(Instr::Input(name.clone())),
+                Statement::Label { name, .. } => {
+                    let target = self.labels[name];
+                    self.finish(Terminator::Jump(target), target);
+                }
+                Statement::Goto { name, .. } => {
+                    let target = self.labels[name];
+                    // Whatever follows a GOTO is only reachable through a label.
+                    let dead = self.new_block();
+                    self.finish(Terminator::Jump(target), dead);
+                }
+                Statement::If { condition, body, .. } => {
+                    let then = self.new_block();
+                    let end = self.new_block();
+                    self.branch(condition, then, end);
+                    self.statements(body);
+                    self.finish(Terminator::Jump(end), end);
+                }
+                Statement::While { condition, body, .. } => {
+                    let cond = self.new_block();
+                    let then = self.new_block();
+                    let end = self.new_block();
+                    self.finish(Terminator::Jump(cond), cond);
+                    self.branch(condition, then, end);
+                    self.statements(body);
+                    self.finish(Terminator::Jump(cond), end);
+                }
+            }
+        }
+    }
+}
+
+impl Ir {
+    /// Lower a parsed program
+    pub fn new(program: &Program) -> Self {
+        let mut b = Builder {
+            blocks: Vec::new(),
+            labels: BTreeMap::new(),
+            cur: 0,
+            order: vec![0],
+            num_temps: 0,
+            number_type: program.number_type,
+            pending: Vec::new(),
+        };
+        b.new_block();
+        b.declare_labels(&program.statements);
+        b.statements(&program.statements);
+        b.blocks[b.cur].terminator = Terminator::Return;
+
+        // Renumber the blocks into source order so that most jumps go to
+        // the block that follows.
+        let mut new_id = vec![0; b.blocks.len()];
+        for (i, &old) in b.order.iter().enumerate() {
+            new_id[old] = i;
+        }
+        let mut blocks = Vec::with_capacity(b.blocks.len());
+        for &old in b.order.iter() {
+            let mut block = b.blocks[old].clone();
+            block.terminator = match block.terminator {
+                Terminator::Jump(t) => Terminator::Jump(new_id[t]),
+                Terminator::Branch { condition, then, otherwise } => Terminator::Branch {
+                    condition,
+                    then: new_id[then],
+                    otherwise: new_id[otherwise],
+                },
+                Terminator::Return => Terminator::Return,
+            };
+            blocks.push(block);
+        }
+        Self {
+            blocks,
+            variables: program.variables.clone(),
+            num_temps: b.num_temps,
+            number_type: program.number_type,
+        }
+    }
+
+    /// A new temporary
+    pub fn temp(&mut self) -> usize {
+        self.num_temps += 1;
+        self.num_temps
+    }
+
+    /// The predecessors of every block, in block order
+    pub fn predecessors(&self) -> Vec<Vec<usize>> {
+        let mut preds = vec![Vec::new(); self.blocks.len()];
+        for (i, block) in self.blocks.iter().enumerate() {
+            for s in block.terminator.successors() {
+                if !preds[s].contains(&i) {
+                    preds[s].push(i);
+                }
+            }
+        }
+        preds
+    }
+
+    /// Whether each block can be reached from the entry
+    pub fn reachable(&self) -> Vec<bool> {
+        let mut reachable = vec![false; self.blocks.len()];
+        let mut stack = vec![0];
+        while let Some(b) = stack.pop() {
+            if !reachable[b] {
+                reachable[b] = true;
+                stack.extend(self.blocks[b].terminator.successors());
+            }
+        }
+        reachable
+    }
+
+    /// The labels jumped to by a GOTO in a reachable block. Only for IR
+    /// fresh from Ir::new: a LABEL falls through into its block from the
+    /// one just before it, and a GOTO is always followed by a new block,
+    /// so every other jump to a labelled block is a GOTO.
+    pub fn goto_targets(&self) -> BTreeSet<String> {
+        let reachable = self.reachable();
+        let mut targets = BTreeSet::new();
+        for (b, block) in self.blocks.iter().enumerate() {
+            if let Terminator::Jump(t) = block.terminator {
+                match &self.blocks[t].label {
+                    Some(label) if reachable[b] && t != b + 1 => {
+                        targets.insert(label.clone());
+                    }
+                    _ => {}
+                }
+            }
+        }
+        targets
+    }
+
+    /// Drop blocks that can't be reached from the entry, along with any
+    /// phi arguments that came from them.
+    pub fn remove_unreachable(&mut self) {
+        let reachable = self.reachable();
+        let mut new_id = vec![0; self.blocks.len()];
+        let mut n = 0;
+        for (i, &r) in reachable.iter().enumerate() {
+            new_id[i] = n;
+            if r {
+                n += 1;
+            }
+        }
+        let blocks = std::mem::take(&mut self.blocks);
+        for (i, mut block) in blocks.into_iter().enumerate() {
+            if !reachable[i] {
+                continue;
+            }
+            block.terminator = match block.terminator {
+                Terminator::Jump(t) => Terminator::Jump(new_id[t]),
+                Terminator::Branch { condition, then, otherwise } => Terminator::Branch {
+                    condition,
+                    then: new_id[then],
+                    otherwise: new_id[otherwise],
+                },
+                Terminator::Return => Terminator::Return,
+            };
+            for phi in block.phis.iter_mut() {
+                phi.args.retain(|(p, _)| reachable[*p]);
+                for (p, _) in phi.args.iter_mut() {
+                    *p = new_id[*p];
+                }
+            }
+            self.blocks.push(block);
+        }
+    }
+
+    /// A Graphviz rendering of the control flow graph
+    pub fn to_dot(&self) -> String {
+        let mut s = String::from("digraph cfg {\n    node [shape=box, fontname=\"monospace\"];\n");
+        for (i, block) in self.blocks.iter().enumerate() {
+            // \l left-justifies each line in the node.
+            let mut text = block_name(i, block);
+            text.push_str(":\\l");
+            for phi in block.phis.iter() {
+                text.push_str(&dot_escape(&phi.to_string()));
+                text.push_str("\\l");
+            }
+            for instr in block.instrs.iter() {
+                text.push_str(&dot_escape(&instr.to_string()));
+                text.push_str("\\l");
+            }
+            text.push_str(&dot_escape(&block.terminator.to_string()));
+            text.push_str("\\l");
+            s.push_str(&format!("    b{} [label=\"{}\"];\n", i, text));
+            match &block.terminator {
+                Terminator::Jump(t) => s.push_str(&format!("    b{} -> b{};\n", i, t)),
+                Terminator::Branch { then, otherwise, .. } => {
+                    s.push_str(&format!("    b{} -> b{} [label=\"true\"];\n", i, then));
+                    s.push_str(&format!("    b{} -> b{} [label=\"false\"];\n", i, otherwise));
+                }
+                Terminator::Return => {}
+            }
+        }
+        s.push_str("}\n");
+        s
+    }
+}
+
+fn block_name(i: usize, block: &Block) -> String {
+    match &block.label {
+        Some(label) => format!("b{} ({})", i, label),
+        None => format!("b{}", i),
+    }
+}
+
+fn dot_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            // Written as an f32 when it is one, so dumps stay short.
+            Operand::Const(c) if *c as f32 as f64 == *c => write!(f, "{:?}", *c as f32),
+            Operand::Const(c) => write!(f, "{:?}", c),
+            Operand::Var(v) => write!(f, "{}", v),
+            Operand::Temp(t) => write!(f, "%t{}", t),
+        }
+    }
+}
+
+impl fmt::Display for Phi {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let args: Vec<String> = self.args.iter().map(|(b, o)| format!("b{}: {}", b, o)).collect();
+        write!(f, "%t{} = phi [{}]", self.dest, args.join(", "))
+    }
+}
+
+impl fmt::Display for Instr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instr::Copy { dest, src } => write!(f, "{} = {}", dest, src),
+            Instr::Neg { dest, src } => write!(f, "{} = -{}", dest, src),
+            Instr::Binary { dest, op, left, right } => write!(f, "{} = {} {} {}", dest, left, op.symbol(), right),
+            Instr::PrintStr { text, newline } => {
+                write!(f, "{} {:?}", if *newline { "println" } else { "print" }, text)
+            }
+            Instr::Print { value, newline } => write!(f, "{} {}", if *newline { "println" } else { "print" }, value),
+            Instr::Input(v) => write!(f, "input {}", v),
+        }
+    }
+}
+
+impl fmt::Display for Terminator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Terminator::Jump(t) => write!(f, "jump b{}", t),
+            Terminator::Branch { condition, then, otherwise } => {
+                write!(f, "branch {}, b{}, b{}", condition, then, otherwise)
+            }
+            Terminator::Return => write!(f, "return"),
+        }
+    }
+}
+
+impl fmt::Display for Ir {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, block) in self.blocks.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            writeln!(f, "{}:", block_name(i, block))?;
+            for phi in block.phis.iter() {
+                writeln!(f, "    {}", phi)?;
+            }
+            for instr in block.instrs.iter() {
+                writeln!(f, "    {}", instr)?;
+            }
+            writeln!(f, "    {}", block.terminator)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,429 @@
+// jsemitter.rs
+// The JavaScript emitter. Produces a script that runs under Node or in a
+// browser. In f32 programs numbers are kept at float precision with
+// Math.fround so that the output matches the C target.
+
+use crate::ast::{BinOp, NumberType, Program};
+use crate::emit::Emitter;
+use crate::ir::{Instr, Operand, Terminator};
+use crate::opt::{self, Passes};
+use std::io::{self, Write};
+
+/// Support code shared by every generated program
+const RUNTIME: &str = r#"
+// Format a number like C's printf("%.2f", x). JavaScript engines don't
+// keep the sign of NaN (V8 drops it in optimized code), so NaN always
+// prints the way x86's default NaN does. C prints "nan" for a negated one.
+function teenyFormat(x) {
+    if (Number.isNaN(x)) {
+        return "-nan";
+    }
+    if (!Number.isFinite(x)) {
+        return x < 0 ? "-inf" : "inf";
+    }
+    // |x| is man * 2^exp exactly, so round |x| * 100 to even with BigInts.
+    const view = new DataView(new ArrayBuffer(8));
+    view.setFloat64(0, Math.abs(x));
+    const bits = view.getBigUint64(0);
+    let exp = Number(bits >> 52n);
+    let man = bits & 0xfffffffffffffn;
+    if (exp === 0) {
+        exp = 1;
+    }
+    else {
+        man |= 1n << 52n;
+    }
+    exp -= 1075;
+    let r;
+    if (exp >= 0) {
+        r = (man << BigInt(exp)) * 100n;
+    }
+    else {
+        const shift = BigInt(-exp);
+        const scaled = man * 100n;
+        r = scaled >> shift;
+        const rem = scaled - (r << shift);
+        const half = 1n << (shift - 1n);
+        if (rem > half || (rem === half && (r & 1n) === 1n)) {
+            r += 1n;
+        }
+    }
+    const digits = r.toString().padStart(3, "0");
+    const sign = x < 0 || Object.is(x, -0) ? "-" : "";
+    return sign + digits.slice(0, -2) + "." + digits.slice(-2);
+}
+
+// Reads numbers the way C's scanf("%lf") does. `read` is called whenever
+// more text is needed and returns a string, or null at end of input.
+// An f32 program rounds what it gets with Math.fround.
+class TeenyInput {
+    constructor(read) {
+        this.read = read;
+        this.buf = "";
+        this.pos = 0;
+        this.eof = false;
+    }
+
+    peek() {
+        while (this.pos >= this.buf.length && !this.eof) {
+            const more = this.read();
+            if (more === null || more === undefined) {
+                this.eof = true;
+            }
+            else {
+                this.buf = this.buf.slice(this.pos) + more;
+                this.pos = 0;
+            }
+        }
+        return this.pos < this.buf.length ? this.buf[this.pos] : null;
+    }
+
+    advance() {
+        const c = this.peek();
+        this.pos += 1;
+        return c;
+    }
+
+    isSpace(c) {
+        return c !== null && " \t\n\v\f\r".includes(c);
+    }
+
+    isDigit(c) {
+        return c !== null && c >= "0" && c <= "9";
+    }
+
+    isHexDigit(c) {
+        return this.isDigit(c) || (c !== null && "abcdefABCDEF".includes(c));
+    }
+
+    digits(isDigit = this.isDigit) {
+        let text = "";
+        while (isDigit.call(this, this.peek())) {
+            text += this.advance();
+        }
+        return text;
+    }
+
+    // Whether the next letters are `letters`, ignoring case. Like glibc,
+    // this consumes the first letter that doesn't match.
+    word(letters) {
+        for (const letter of letters) {
+            const c = this.advance();
+            if (c === null || c.toLowerCase() !== letter) {
+                return false;
+            }
+        }
+        return true;
+    }
+
+    // The digits of an exponent introduced by one of `letters`, with their
+    // sign. The letter is consumed even if no digits follow, which leaves
+    // the exponent out.
+    exponent(letters) {
+        if (this.peek() === null || !letters.includes(this.peek())) {
+            return "";
+        }
+        this.advance();
+        let sign = "";
+        if (this.peek() === "+" || this.peek() === "-") {
+            sign = this.advance();
+        }
+        const digits = this.digits();
+        return digits === "" ? "" : sign + digits;
+    }
+
+    // Skip what scanf("%*s") does after a failed read.
+    skip() {
+        while (this.isSpace(this.peek())) {
+            this.advance();
+        }
+        while (this.peek() !== null && !this.isSpace(this.peek())) {
+            this.advance();
+        }
+        return 0;
+    }
+
+    // The integer with these hexadecimal digits times 2^exp, rounded to
+    // the nearest double, ties to even, as strtod does.
+    hexValue(digits, exp) {
+        let man = BigInt("0x" + digits);
+        if (man === 0n) {
+            return 0;
+        }
+        const lsb = Math.max(exp + man.toString(2).length - 53, -1074);
+        if (lsb > exp) {
+            const shift = BigInt(lsb - exp);
+            const rem = man & ((1n << shift) - 1n);
+            const half = 1n << (shift - 1n);
+            man >>= shift;
+            if (rem > half || (rem === half && (man & 1n) === 1n)) {
+                man += 1n;
+            }
+            exp = lsb;
+        }
+        // In two steps, since 2^exp on its own may not fit in a double.
+        const half = Math.trunc(exp / 2);
+        return Number(man) * 2 ** half * 2 ** (exp - half);
+    }
+
+    // The new value of `old` after
+    //     if (0 == scanf("%lf", &old)) { old = 0; scanf("%*s"); }
+    readNum(old) {
+        while (this.isSpace(this.peek())) {
+            this.advance();
+        }
+        if (this.peek() === null) {
+            return old;
+        }
+        let sign = "";
+        if (this.peek() === "+" || this.peek() === "-") {
+            sign = this.advance();
+        }
+        const letter = this.peek() === null ? "" : this.peek().toLowerCase();
+        if (letter === "i") {
+            if (!this.word("inf")) {
+                return this.skip();
+            }
+            if (this.peek() !== null && this.peek().toLowerCase() === "i" && !this.word("inity")) {
+                return this.skip();
+            }
+            return Number(sign + "Infinity");
+        }
+        if (letter === "n") {
+            return this.word("nan") ? NaN : this.skip();
+        }
+        let n = this.digits();
+        if (n === "0" && (this.peek() === "x" || this.peek() === "X")) {
+            this.advance();
+            let digits = this.digits(this.isHexDigit);
+            let fraction = "";
+            if (this.peek() === ".") {
+                this.advance();
+                fraction = this.digits(this.isHexDigit);
+            }
+            else if (digits === "") {
+                return this.skip();
+            }
+            let value = 0;
+            if (digits + fraction !== "") {
+                const exp = Math.min(Math.max(Number(this.exponent("pP")), -100000), 100000);
+                value = this.hexValue(digits + fraction, exp - 4 * fraction.length);
+            }
+            return sign === "-" ? -value : value;
+        }
+        if (this.peek() === ".") {
+            this.advance();
+            n += "." + this.digits();
+        }
+        if (n.replace(".", "") !== "") {
+            const exp = this.exponent("eE");
+            return Number(sign + n + (exp === "" ? "" : "e" + exp));
+        }
+        return this.skip();
+    }
+}
+"#;
+
+/// Running the file directly with Node reads stdin and writes stdout.
+/// Stdin is read only when an INPUT needs it, so that output before the
+/// first INPUT shows up straight away on a terminal.
+const NODE_MAIN: &str = r#"
+if (typeof module !== "undefined") {
+    module.exports = { run };
+    if (require.main === module) {
+        const fs = require("fs");
+        const decoder = new (require("string_decoder").StringDecoder)("utf8");
+        const buffer = Buffer.alloc(65536);
+        run(() => {
+            for (;;) {
+                let n;
+                try {
+                    n = fs.readSync(0, buffer, 0, buffer.length, null);
+                }
+                catch (e) {
+                    if (e.code === "EAGAIN") {
+                        continue;
+                    }
+                    if (e.code === "EOF") {
+                        return null;
+                    }
+                    throw e;
+                }
+                if (n === 0) {
+                    return null;
+                }
+                const text = decoder.write(buffer.subarray(0, n));
+                if (text !== "") {
+                    return text;
+                }
+            }
+        }, (text) => process.stdout.write(text));
+    }
+}"#;
+
+pub struct JsEmitter {
+    passes: Passes,
+    header: String,
+    code: String,
+    number_type: NumberType,
+}
+
+impl JsEmitter {
+    pub fn new(passes: Passes) -> Self {
+        Self {
+            passes,
+            header: String::new(),
+            code: String::new(),
+            number_type: NumberType::F32,
+        }
+    }
+
+    /// Round the result of an operation to the number type
+    fn round(&self, e: &str) -> String {
+        match self.number_type {
+            NumberType::F32 => format!("Math.fround({})", e),
+            NumberType::F64 => e.to_string(),
+        }
+    }
+
+    fn line(&mut self, indent: usize, code: &str) {
+        self.emit(&"    ".repeat(indent));
+        self.emit_line(code);
+    }
+
+    fn instruction(&mut self, instr: &Instr) {
+        match instr {
+            Instr::PrintStr { text, newline } => {
+                let mut text = text.clone();
+                if *newline {
+                    text.push('\n');
+                }
+                self.line(4, &format!("write({});", string_literal(&text)));
+            }
+            Instr::Print { value, newline } => {
+                let nl = if *newline { " + \"\\n\"" } else { "" };
+                self.line(4, &format!("write(teenyFormat({}){});", operand(value), nl));
+            }
+            Instr::Copy { dest, src } => {
+                self.line(4, &format!("{} = {};", operand(dest), operand(src)));
+            }
+            Instr::Neg { dest, src } => {
+                self.line(4, &format!("{} = -{};", operand(dest), operand(src)));
+            }
+            Instr::Binary { dest, op, left, right } if op.is_comparison() => {
+                let symbol = match op {
+                    BinOp::EqEq => "===",
+                    BinOp::NotEq => "!==",
+                    _ => op.symbol(),
+                };
+                let c = format!("{} {} {}", operand(left), symbol, operand(right));
+                self.line(4, &format!("{} = {} ? 1 : 0;", operand(dest), c));
+            }
+            Instr::Binary { dest, op, left, right } => {
+                let e = format!("{} {} {}", operand(left), op.symbol(), operand(right));
+                let e = self.round(&e);
+                self.line(4, &format!("{} = {};", operand(dest), e));
+            }
+            Instr::Input(name) => {
+                let e = self.round(&format!("input.readNum(v_{})", name));
+                self.line(4, &format!("v_{} = {};", name, e));
+            }
+        }
+    }
+}
+
+/// A double quoted JavaScript string
+fn string_literal(text: &str) -> String {
+    let mut s = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => s.push_str("\\\""),
+            '\\' => s.push_str("\\\\"),
+            '\n' => s.push_str("\\n"),
+            c if (c as u32) < 0x20 => s.push_str(&format!("\\x{:02x}", c as u32)),
+            c => s.push(c),
+        }
+    }
+    s.push('"');
+    s
+}
+
+/// Render an operand. Constants are written as doubles, which every f32
+/// value is exactly.
+fn operand(o: &Operand) -> String {
+    match o {
+        Operand::Const(c) if c.is_nan() => String::from("NaN"),
+        Operand::Const(c) if c.is_infinite() => {
+            String::from(if *c < 0.0 { "(-Infinity)" } else { "Infinity" })
+        }
+        Operand::Const(c) if c.is_sign_negative() => format!("({:?})", c),
+        Operand::Const(c) => format!("{:?}", c),
+        Operand::Var(v) => format!("v_{}", v),
+        Operand::Temp(t) => format!("t{}", t),
+    }
+}
+
+impl Emitter for JsEmitter {
+    fn emit(&mut self, code: &str) {
+        self.code.push_str(code);
+    }
+
+    fn emit_line(&mut self, code: &str) {
+        self.emit(code);
+        self.code.push('\n');
+    }
+
+    fn header_line(&mut self, code: &str) {
+        self.header.push_str(code);
+        self.header.push('\n');
+    }
+
+    fn write(&self, out: &mut dyn Write) -> io::Result<()> {
+        out.write_all(self.header.as_bytes())?;
+        out.write_all(self.code.as_bytes())
+    }
+
+    fn program(&mut self, program: &Program) {
+        let ir = opt::lower(program, &self.passes);
+        self.number_type = ir.number_type;
+        self.header_line("// Generated by ttrust.");
+        self.header_line("\"use strict\";");
+        self.header_line(RUNTIME);
+
+        self.emit_line("// Run the program. INPUT pulls text from `read` (see TeenyInput) and");
+        self.emit_line("// PRINT hands strings to `write`.");
+        self.emit_line("function run(read, write) {");
+        self.line(1, "const input = new TeenyInput(read);");
+        for v in ir.variables.iter() {
+            self.line(1, &format!("let v_{} = 0;", v));
+        }
+        for t in 1..=ir.num_temps {
+            self.line(1, &format!("let t{} = 0;", t));
+        }
+        self.line(1, "let state = 0;");
+        self.line(1, "for (;;) {");
+        self.line(2, "switch (state) {");
+        for (i, block) in ir.blocks.iter().enumerate() {
+            self.line(3, &format!("case {}:", i));
+            for instr in block.instrs.iter() {
+                self.instruction(instr);
+            }
+            match &block.terminator {
+                Terminator::Jump(t) => {
+                    self.line(4, &format!("state = {};", t));
+                    self.line(4, "break;");
+                }
+                Terminator::Branch { condition, then, otherwise } => {
+                    let c = operand(condition);
+                    self.line(4, &format!("state = {} !== 0 ? {} : {};", c, then, otherwise));
+                    self.line(4, "break;");
+                }
+                Terminator::Return => self.line(4, "return;"),
+            }
+        }
+        self.line(2, "}");
+        self.line(1, "}");
+        self.emit_line("}");
+        self.emit_line(NODE_MAIN);
+    }
+}
@@ -1,52 +1,147 @@
+// lex.rs
+// The lexer. The source is read a character at a time, so UTF-8 in strings
+// and comments comes through untouched, and positions are counted in
+// characters.
 
-use crate::token::{Token, TokenType};
+use crate::ast::NumberType;
+use crate::diagnostic::Diagnostic;
+use crate::token::{Dialect, Span, Token, TokenType};
+#[derive(Clone)]
 pub struct Lexer {
     source: String,
+    /// Each character of the source with its byte offset
+    chars: Vec<(usize, char)>,
+    index: usize,
     pub cur_char: char,
-    cur_pos: i32,
+    /// Byte offset of the current character
+    cur_pos: usize,
+    /// Line and column of the current character, counting from 1
+    pub line: usize,
+    pub column: usize,
+    /// Lints named in `# ttrust: allow(...)` comments
+    pub allowed: Vec<String>,
+    dialect: Dialect,
+    /// Whether whitespace and comments are returned as tokens
+    trivia: bool,
+    /// Number literals too large for it are rejected
+    number_type: NumberType,
+    /// Where the last run of comments looked through by is_doc_comment
+    /// ends, as an index into `chars`, and whether a LABEL comes next
+    comments_end: Option<(usize, bool)>,
 }
 
 impl Lexer {
     /// Create a new lexer to analyze the given input
-    pub fn new(input: &String) -> Self {
+    pub fn new(input: &str) -> Self {
+        Self::with_dialect(input, Dialect::Strict)
+    }
+
+    /// Create a lexer that spells keywords the way `dialect` says
+    pub fn with_dialect(input: &str, dialect: Dialect) -> Self {
+        // Every statement ends with a newline, including the last.
+        let mut source = input.to_string();
+        if !source.ends_with('\n') {
+            source.push('\n');
+        }
+        let chars = source.char_indices().collect();
         let mut s = Self {
-            source: input.clone() + "\n",
-            cur_char: ' ' as char,
-            cur_pos: -1,
+            source,
+            chars,
+            index: 0,
+            cur_char: '\0',
+            cur_pos: 0,
+            line: 1,
+            column: 1,
+            allowed: Vec::new(),
+            dialect,
+            trivia: false,
+            number_type: NumberType::F32,
+            comments_end: None,
         };
-        s.next_char();
+        s.set_current();
         s
     }
 
+    /// Reject number literals that `number_type` can't hold
+    pub fn number_type(mut self, number_type: NumberType) -> Self {
+        self.number_type = number_type;
+        self
+    }
+
+    /// Return whitespace and comments as tokens too, so that the tokens
+    /// cover every byte of the source
+    pub fn keep_trivia(mut self) -> Self {
+        self.trivia = true;
+        self
+    }
+
+    fn set_current(&mut self) {
+        match self.chars.get(self.index) {
+            Some(&(pos, c)) => {
+                self.cur_pos = pos;
+                self.cur_char = c;
+            }
+            None => {
+                self.cur_pos = self.source.len();
+                self.cur_char = '\0';
+            }
+        }
+    }
+
     /// Skip to the next character
     pub fn next_char(&mut self) {
-        self.cur_pos += 1;
-        let p = self.cur_pos as usize;
-        if p >= self.source.len() {
-            self.cur_char = '\0';
+        if self.cur_char == '\n' {
+            self.line += 1;
+            self.column = 1;
         }
         else {
-            self.cur_char = self.source.as_bytes()[p] as char;
+            self.column += 1;
         }
+        self.index += 1;
+        self.set_current();
     }
 
     /// Return the next character without consuming it.
     pub fn peek(&self) -> char {
-        let p = self.cur_pos as usize + 1;
-        if p >= self.source.len() {
-            '\0'
-        }
-        else {
-            self.source.as_bytes()[p] as char
+        match self.chars.get(self.index + 1) {
+            Some(&(_, c)) => c,
+            None => '\0',
         }
     }
 
+    /// Byte offset just past the current character
+    fn next_pos(&self) -> usize {
+        self.cur_pos + self.cur_char.len_utf8()
+    }
+
+    /// Where the current character is, for error messages
+    pub fn location(&self) -> String {
+        format!("line {}, column {}", self.line, self.column)
+    }
+
     /// Get the type of token we're looking at
-    pub fn get_token(&mut self) -> Token {
-        // Skip whitespace
-        self.skip_whitespace();
-        // Skip comments
-        self.skip_comment();
+    pub fn get_token(&mut self) -> Result<Token, Diagnostic> {
+        // Skip whitespace and comments, or return them as tokens when
+        // keeping trivia. A block comment can be followed by more of either
+        // on the same line.
+        loop {
+            let start = self.start_span();
+            self.skip_whitespace();
+            if self.trivia && self.cur_pos > start.start {
+                return Ok(self.trivia_token(TokenType::Whitespace, start));
+            }
+            if self.is_doc_comment() {
+                let token = self.doc_comment();
+                return Ok(self.finish_span(token, start));
+            }
+            if !self.skip_comment()? {
+                break;
+            }
+            if self.trivia {
+                return Ok(self.trivia_token(TokenType::Comment, start));
+            }
+        }
+        let start = self.start_span();
         // Build a string that will be the token itself
         let mut s = String::new();
         s.push(self.cur_char);
@@ -88,50 +183,27 @@ impl Lexer {
                             TokenType::NotEq
                         }
                         else {
-                            abort!("Expected !=, got !{}", self.peek());
+                            abort!("Expected !=, got '!' followed by {:?} at {}", self.peek(), self.location());
                         },
                 '"' => {
-                    self.next_char();
-                    let start_pos = self.cur_pos;
-                    while self.cur_char != '"' {
-                        match self.cur_char {
-                            '\r' | '\n' | '\t' | '\\' | '%' => {
-                                abort!("Illegal character in string.");
-                            }
-                            _ => {
-                                self.next_char();
-                            }
-                        }
-                    }
-                    let tok_text = self.source.get(start_pos as usize..self.cur_pos as usize).unwrap();
-                    s = String::from(tok_text);
+                    s = self.string()?;
                     TokenType::String
                 },
                 '0'..='9' => {
-                    let start_pos = self.cur_pos;
-                    while self.peek().is_ascii_digit() {
-                        self.next_char();
-                    }
-                    if self.peek() == '.' {
-                        self.next_char();
-                        if !self.peek().is_ascii_digit() {
-                            abort!("Illegal character in number.");
-                        }
-                        while self.peek().is_ascii_digit() {
-                            self.next_char();
-                        }
-                    }
-                    let tok_text = self.source.get(start_pos as usize..(self.cur_pos as usize + 1)).unwrap();
-                    s = String::from(tok_text);
+                    s = self.number()?;
+                    TokenType::Number
+                }
+                '.' if self.peek().is_ascii_digit() => {
+                    s = self.number()?;
                     TokenType::Number
                 }
-                'a'..='z' | 'A'..='Z' => {
+                'a'..='z' | 'A'..='Z' | '_' => {
                     let start_pos = self.cur_pos;
-                    while self.peek().is_ascii_alphanumeric() {
+                    while self.peek().is_ascii_alphanumeric() || self.peek() == '_' {
                         self.next_char();
                     }
-                    let tok_text = self.source.get(start_pos as usize..(self.cur_pos as usize + 1)).unwrap();
-                    let keyword = Token::check_if_keyword(tok_text);
+                    let tok_text = self.source.get(start_pos..self.next_pos()).unwrap();
+                    let keyword = Token::keyword(tok_text, self.dialect);
                     if keyword == TokenType::Unknown {
                         // identifier
                         s = String::from(tok_text);
@@ -144,10 +216,183 @@ impl Lexer {
                 }
                 '\n' => TokenType::Newline,
                 '\0' => TokenType::Eof,
-                _ => abort!("Unknown token: '{}'", self.cur_char),
+                _ => abort!("Unknown token: {:?} at {}", self.cur_char, self.location()),
+            };
+        self.next_char();
+        let token = Token::new(&s, token_type);
+        Ok(self.finish_span(token, start))
+    }
+
+    /// A whitespace or comment token, whose text is exactly the source
+    fn trivia_token(&self, kind: TokenType, start: Span) -> Token {
+        let token = Token::new(&self.source[start.start..self.cur_pos], kind);
+        self.finish_span(token, start)
+    }
+
+    /// A span starting at the current character
+    fn start_span(&self) -> Span {
+        Span {
+            start: self.cur_pos,
+            end: self.cur_pos,
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    /// Give a token its span, which ends where the lexer is now
+    fn finish_span(&self, mut token: Token, mut span: Span) -> Token {
+        span.end = self.cur_pos;
+        token.span = span;
+        token
+    }
+
+    /// Read a number literal, leaving the lexer on its last character.
+    /// Returns the value written in decimal without underscores, which is
+    /// how the rest of the compiler expects to parse it.
+    fn number(&mut self) -> Result<String, Diagnostic> {
+        let start = self.location();
+        if self.cur_char == '0' && matches!(self.peek(), 'x' | 'b') {
+            self.next_char();
+            let (radix, name) = if self.cur_char == 'x' { (16, "hex") } else { (2, "binary") };
+            let mut digits = String::new();
+            self.digits(radix, &mut digits)?;
+            if digits.is_empty() {
+                abort!("Expected {} digits after '0{}' at {}", name, self.cur_char, start);
+            }
+            self.end_of_number()?;
+            // Whole numbers are written out in full so that the conversion
+            // to float rounds the same way for every radix.
+            return match u128::from_str_radix(&digits, radix) {
+                Ok(value) => self.in_range(value.to_string(), &start),
+                Err(_) => abort!("Number too large at {}", start),
             };
+        }
+
+        let mut text = String::new();
+        if self.cur_char == '.' {
+            text.push('0');
+        }
+        else {
+            text.push(self.cur_char);
+            self.digits(10, &mut text)?;
+            if self.peek() == '.' {
+                self.next_char();
+            }
+        }
+        if self.cur_char == '.' {
+            text.push('.');
+            if !self.peek().is_ascii_digit() {
+                abort!("Expected a digit after '.' in number at {}", self.location());
+            }
+            self.digits(10, &mut text)?;
+        }
+        if matches!(self.peek(), 'e' | 'E') {
+            self.next_char();
+            text.push('e');
+            if matches!(self.peek(), '+' | '-') {
+                self.next_char();
+                text.push(self.cur_char);
+            }
+            if !self.peek().is_ascii_digit() {
+                abort!("Expected digits in the exponent of a number at {}", self.location());
+            }
+            self.digits(10, &mut text)?;
+        }
+        self.end_of_number()?;
+        self.in_range(text, &start)
+    }
+
+    /// The text of a number literal, unless it rounds to infinity
+    fn in_range(&self, text: String, start: &str) -> Result<String, Diagnostic> {
+        match self.number_type.parse(&text) {
+            Some(value) if value.is_finite() => Ok(text),
+            _ => abort!("Number too large at {}", start),
+        }
+    }
+
+    /// Read the digits that follow, skipping underscores between them
+    fn digits(&mut self, radix: u32, text: &mut String) -> Result<(), Diagnostic> {
+        while self.peek().is_digit(radix) || self.peek() == '_' {
+            self.next_char();
+            if self.cur_char == '_' {
+                if !self.peek().is_digit(radix) {
+                    abort!("Expected a digit after '_' in number at {}", self.location());
+                }
+            }
+            else {
+                text.push(self.cur_char);
+            }
+        }
+        Ok(())
+    }
+
+    /// Make sure a number isn't followed by something that looks like
+    /// part of it, such as the 2 in 0b102
+    fn end_of_number(&mut self) -> Result<(), Diagnostic> {
+        let c = self.peek();
+        if c.is_alphanumeric() || c == '_' || c == '.' {
+            self.next_char();
+            abort!("Invalid character '{}' in number at {}", c, self.location());
+        }
+        Ok(())
+    }
+
+    /// Read the rest of a string literal, up to the closing quote, and
+    /// return its value with the escape sequences decoded.
+    fn string(&mut self) -> Result<String, Diagnostic> {
+        let start = self.location();
+        self.next_char();
+        let mut value = String::new();
+        // Text without escapes is copied straight from the source, which
+        // keeps any UTF-8 in it intact.
+        let mut start_pos = self.cur_pos;
+        while self.cur_char != '"' {
+            match self.cur_char {
+                '\n' | '\0' => abort!("Unterminated string starting at {}", start),
+                '\\' => {
+                    value.push_str(&self.source[start_pos..self.cur_pos]);
+                    self.next_char();
+                    match self.cur_char {
+                        '\n' | '\0' => abort!("Unterminated string starting at {}", start),
+                        'n' => value.push('\n'),
+                        't' => value.push('\t'),
+                        '"' => value.push('"'),
+                        '\\' => value.push('\\'),
+                        'u' => value.push(self.unicode_escape()?),
+                        c => abort!("Unknown escape sequence '\\{}' in string at {}", c, self.location()),
+                    }
+                    self.next_char();
+                    start_pos = self.cur_pos;
+                }
+                _ => self.next_char(),
+            }
+        }
+        value.push_str(&self.source[start_pos..self.cur_pos]);
+        Ok(value)
+    }
+
+    /// Decode the `{...}` of a `\u{...}` escape, leaving the lexer on the
+    /// closing brace
+    fn unicode_escape(&mut self) -> Result<char, Diagnostic> {
         self.next_char();
-        Token::new(&s, token_type)
+        if self.cur_char != '{' {
+            abort!("Expected '{{' after \\u in string at {}", self.location());
+        }
+        let start_pos = self.next_pos();
+        while self.peek().is_ascii_hexdigit() {
+            self.next_char();
+        }
+        let digits = self.source[start_pos..self.next_pos()].to_string();
+        if self.peek() != '}' || digits.is_empty() || digits.len() > 6 {
+            abort!("Expected 1 to 6 hex digits and '}}' in \\u{{...}} escape at {}", self.location());
+        }
+        self.next_char();
+        match u32::from_str_radix(&digits, 16).ok().and_then(char::from_u32) {
+            // C strings end at the first NUL.
+            Some('\0') => abort!("Strings can't contain \\u{{{}}} at {}", digits, self.location()),
+            Some(c) => Ok(c),
+            None => abort!("\\u{{{}}} is not a Unicode character at {}", digits, self.location()),
+        }
     }
 
     /// Skip whitespace
@@ -157,11 +402,160 @@ impl Lexer {
         }
     }
 
-    pub fn skip_comment(&mut self) {
-        if self.cur_char == '#' {
+    /// Whether a `##` doc comment starts here. It has to start its line and
+    /// be followed by a LABEL, with nothing but blank lines, comments and
+    /// more doc comments in between. Anywhere else `##` is an ordinary
+    /// comment, as are three or more `#`, so banners of them still work.
+    fn is_doc_comment(&mut self) -> bool {
+        if self.cur_char != '#' || self.peek() != '#' || self.chars.get(self.index + 2).map(|&(_, c)| c) == Some('#') {
+            return false;
+        }
+        let starts_line = self.chars[..self.index]
+            .iter()
+            .rev()
+            .take_while(|&&(_, c)| c != '\n')
+            .all(|&(_, c)| c == ' ' || c == '\t' || c == '\r');
+        if !starts_line {
+            return false;
+        }
+        // Every `##` line in a run of comments is followed by the same
+        // token, so the run is only looked through once.
+        match self.comments_end {
+            Some((end, label)) if self.index < end => label,
+            _ => {
+                let end = self.skip_comments_ahead(self.index);
+                let label = end.is_some_and(|end| self.label_at(end));
+                self.comments_end = end.map(|end| (end, label));
+                label
+            }
+        }
+    }
+
+    /// The index in `chars` of the first character after `index` that isn't
+    /// whitespace or in a comment. None for an unterminated block comment.
+    fn skip_comments_ahead(&self, mut index: usize) -> Option<usize> {
+        let at = |i: usize| self.chars.get(i).map_or('\0', |&(_, c)| c);
+        loop {
+            match (at(index), at(index + 1)) {
+                (' ' | '\t' | '\r' | '\n', _) => index += 1,
+                ('#', '[') => {
+                    let mut depth = 0;
+                    loop {
+                        match (at(index), at(index + 1)) {
+                            ('#', '[') => {
+                                depth += 1;
+                                index += 1;
+                            }
+                            (']', '#') => {
+                                depth -= 1;
+                                index += 1;
+                                if depth == 0 {
+                                    break;
+                                }
+                            }
+                            ('\0', _) => return None,
+                            _ => {}
+                        }
+                        index += 1;
+                    }
+                    index += 1;
+                }
+                ('#', _) => {
+                    while at(index) != '\n' && at(index) != '\0' {
+                        index += 1;
+                    }
+                }
+                _ => return Some(index),
+            }
+        }
+    }
+
+    /// Whether the word starting at `index` in `chars` is the keyword LABEL
+    fn label_at(&self, index: usize) -> bool {
+        let word = self.chars[index..]
+            .iter()
+            .take_while(|&&(_, c)| c.is_ascii_alphanumeric() || c == '_')
+            .map(|&(_, c)| c)
+            .collect::<String>();
+        Token::keyword(&word, self.dialect) == TokenType::Label
+    }
+
+    /// Read a doc comment up to the end of the line
+    fn doc_comment(&mut self) -> Token {
+        self.next_char();
+        let start_pos = self.next_pos();
+        while self.cur_char != '\n' {
+            self.next_char();
+        }
+        let text = &self.source[start_pos..self.cur_pos];
+        let text = text.strip_prefix(' ').unwrap_or(text).trim_end();
+        Token::new(text, TokenType::DocComment)
+    }
+
+    /// Skip a `#[ ... ]#` comment, which may contain others
+    fn skip_block_comment(&mut self) -> Result<(), Diagnostic> {
+        let start = self.location();
+        let mut depth = 0;
+        loop {
+            match (self.cur_char, self.peek()) {
+                ('#', '[') => {
+                    depth += 1;
+                    self.next_char();
+                }
+                (']', '#') => {
+                    depth -= 1;
+                    self.next_char();
+                    if depth == 0 {
+                        self.next_char();
+                        return Ok(());
+                    }
+                }
+                ('\0', _) => abort!("Unterminated block comment starting at {}", start),
+                _ => {}
+            }
+            self.next_char();
+        }
+    }
+
+    /// Skip a comment, noting any lints a pragma in it allows. Returns
+    /// whether there was one.
+    pub fn skip_comment(&mut self) -> Result<bool, Diagnostic> {
+        if self.cur_char != '#' {
+            return Ok(false);
+        }
+        if self.peek() == '[' {
+            self.skip_block_comment()?;
+        }
+        else {
+            let start_pos = self.next_pos();
             while self.cur_char != '\n' {
                 self.next_char();
             }
+            let text = self.source[start_pos..self.cur_pos].trim();
+            if let Some(pragma) = text.strip_prefix("ttrust:") {
+                let names = pragma
+                    .trim()
+                    .strip_prefix("allow(")
+                    .and_then(|p| p.strip_suffix(')'));
+                match names {
+                    Some(names) => self.allowed.extend(names.split(',').map(|n| n.trim().to_string())),
+                    None => abort!("Malformed pragma '{}' on line {}", text, self.line),
+                }
+            }
         }
+        Ok(true)
     }
-}
\ No newline at end of file
+}
+
+/// The tokens of the source, up to but not including Eof. Collecting into
+/// a `Result` stops at the first error.
+impl Iterator for Lexer {
+    type Item = Result<Token, Diagnostic>;
+
+    fn next(&mut self) -> Option<Result<Token, Diagnostic>> {
+        match self.get_token() {
+            Ok(token) if token.kind == TokenType::Eof => None,
+            result => Some(result),
+        }
+    }
+}
@@ -0,0 +1,305 @@
+// lib.rs
+// The compiler as a library. `compile_str` runs the whole pipeline on a
+// string and hands back the generated code, so build scripts and tests can
+// compile Teeny without running the ttrust binary.
+
+/// Stop with an error. Nothing in the library exits the process; the error
+/// goes back up to the caller of compile_str.
+macro_rules! abort
+{
+	($fmt:expr) => ({
+            return Err($crate::diagnostic::Diagnostic::error(format!($fmt)))
+			});
+	($fmt:expr, $($args:tt)+) => ({
+            return Err($crate::diagnostic::Diagnostic::error(format!($fmt, $($args)+)))
+			});
+}
+
+use crate::cemitter::CEmitter;
+use crate::lex::Lexer;
+use crate::lint::Lints;
+use crate::opt::Passes;
+use crate::parse::Parser;
+use crate::token::Token;
+
+pub use crate::ast::NumberType;
+pub use crate::diagnostic::{Diagnostic, Diagnostics, Severity};
+pub use crate::emit::Target;
+pub use crate::lint::Level;
+pub use crate::token::Dialect;
+
+/// What to produce instead of code, for --emit
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum EmitKind {
+    Tokens,
+    Cst,
+    Ast,
+    AstJson,
+    Ir,
+    CfgDot,
+    Regalloc,
+}
+
+impl EmitKind {
+    /// The name used with --emit
+    pub fn by_name(name: &str) -> Option<EmitKind> {
+        match name {
+            "tokens" => Some(EmitKind::Tokens),
+            "cst" => Some(EmitKind::Cst),
+            "ast" => Some(EmitKind::Ast),
+            "ast-json" => Some(EmitKind::AstJson),
+            "ir" => Some(EmitKind::Ir),
+            "cfg-dot" => Some(EmitKind::CfgDot),
+            "regalloc" => Some(EmitKind::Regalloc),
+            _ => None,
+        }
+    }
+
+    /// Where the dump goes when no -o is given
+    pub fn default_path(&self) -> &'static str {
+        match self {
+            EmitKind::Tokens => "out.tokens",
+            EmitKind::Cst => "out.cst",
+            EmitKind::Ast => "out.ast",
+            EmitKind::AstJson => "out.json",
+            EmitKind::Ir => "out.ir",
+            EmitKind::CfgDot => "out.dot",
+            EmitKind::Regalloc => "out.regs",
+        }
+    }
+}
+
+/// How to compile, built up a setting at a time:
+///
+/// ```
+/// use ttrust::{CompileOptions, Target};
+/// let options = CompileOptions::new().target(Target::Llvm).opt_level(2);
+/// let output = ttrust::compile_str("PRINT 1 + 2\n", &options).unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct CompileOptions {
+    target: Target,
+    opt_level: u8,
+    /// -f flags, applied in order after the optimization level
+    passes: Vec<String>,
+    /// Lint levels, applied in order before any pragmas in the source
+    lints: Vec<(String, Level)>,
+    dialect: Dialect,
+    number_type: NumberType,
+    arch: String,
+    emit: Option<EmitKind>,
+    /// The source file named in #line directives in C output
+    line_file: Option<String>,
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CompileOptions {
+    /// C at -O0 with f32 numbers, the default lint levels and strict
+    /// keywords
+    pub fn new() -> Self {
+        Self {
+            target: Target::C,
+            opt_level: 0,
+            passes: Vec::new(),
+            lints: Vec::new(),
+            dialect: Dialect::Strict,
+            number_type: NumberType::F32,
+            arch: String::from("x86-64"),
+            emit: None,
+            line_file: None,
+        }
+    }
+
+    pub fn target(mut self, target: Target) -> Self {
+        self.target = target;
+        self
+    }
+
+    /// 0, 1 (folds constants) or 2 (also runs every IR pass)
+    pub fn opt_level(mut self, level: u8) -> Self {
+        self.opt_level = level;
+        self
+    }
+
+    /// Turn an IR pass on or off the way -f<name> and -fno-<name> do
+    pub fn pass(mut self, flag: &str) -> Self {
+        self.passes.push(flag.to_string());
+        self
+    }
+
+    /// Set the level of a lint, or of every warning for "warnings"
+    pub fn lint(mut self, name: &str, level: Level) -> Self {
+        self.lints.push((name.to_string(), level));
+        self
+    }
+
+    pub fn dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    /// Whether values are floats or doubles, in the generated code and
+    /// when folding constants
+    pub fn number_type(mut self, number_type: NumberType) -> Self {
+        self.number_type = number_type;
+        self
+    }
+
+    /// The register file used by EmitKind::Regalloc
+    pub fn arch(mut self, arch: &str) -> Self {
+        self.arch = arch.to_string();
+        self
+    }
+
+    /// Produce a dump of the tokens, a tree or the IR instead of code
+    pub fn emit(mut self, kind: EmitKind) -> Self {
+        self.emit = Some(kind);
+        self
+    }
+
+    /// Mark each statement in C output with a #line directive naming
+    /// `file`, so that errors from the C compiler point at the Teeny source
+    pub fn line_directives(mut self, file: &str) -> Self {
+        self.line_file = Some(file.to_string());
+        self
+    }
+
+    /// Where the command line writes the output when no -o is given
+    pub fn default_path(&self) -> &'static str {
+        match self.emit {
+            Some(kind) => kind.default_path(),
+            None => self.target.default_path(),
+        }
+    }
+}
+
+/// What a successful compile produces
+#[derive(Clone, Debug)]
+pub struct Output {
+    /// The generated code, or the dump asked for with CompileOptions::emit
+    pub code: String,
+    pub warnings: Vec<Diagnostic>,
+    /// The IR after each pass, with -fdump-passes. Empty for C, which
+    /// doesn't go through the IR.
+    pub pass_dumps: String,
+}
+
+/// Compile a Teeny program
+pub fn compile_str(source: &str, options: &CompileOptions) -> Result<Output, Diagnostics> {
+    let mut lints = Lints::new();
+    let mut pass_dumps = String::new();
+    let result = compile(source, options, &mut lints, &mut pass_dumps);
+    let warnings = lints.take_warnings();
+    match result {
+        Ok(code) => Ok(Output { code, warnings, pass_dumps }),
+        Err(error) => Err(Diagnostics { warnings, error }),
+    }
+}
+
+fn compile(source: &str, options: &CompileOptions, lints: &mut Lints, pass_dumps: &mut String) -> Result<String, Diagnostic> {
+    // -f flags override the optimization level wherever they appear.
+    let mut passes = Passes::for_level(options.opt_level);
+    for flag in options.passes.iter() {
+        if !passes.set(flag) {
+            abort!("Unknown switch -f{}", flag);
+        }
+    }
+    for (name, level) in options.lints.iter() {
+        if !lints.set(name, *level) {
+            abort!("Unknown lint '{}'", name);
+        }
+    }
+
+    let mut lexer = Lexer::with_dialect(source, options.dialect).number_type(options.number_type);
+    match options.emit {
+        // Before parsing, so that source the parser rejects can be looked at.
+        Some(EmitKind::Tokens) => {
+            let tokens = lexer.collect::<Result<Vec<Token>, _>>()?;
+            return Ok(tokens.iter().map(|t| format!("{}\n", t)).collect());
+        }
+        Some(EmitKind::Cst) => return Ok(cst::parse(source, options.dialect)?.dump()),
+        _ => {}
+    }
+    let mut program = Parser::new(&mut lexer)?.program()?;
+    program.number_type = options.number_type;
+    // Straight from the parser, before any checks or rewrites.
+    match options.emit {
+        Some(EmitKind::Ast) => return Ok(astdump::tree(&program)),
+        Some(EmitKind::AstJson) => return Ok(astdump::json(&program)),
+        _ => {}
+    }
+    // Pragmas in the source override the options.
+    for name in lexer.allowed.iter() {
+        if !lints.set(name, Level::Allow) {
+            abort!("Unknown lint '{}' in pragma", name);
+        }
+    }
+    definite::check_definite_assignment(&program)?;
+    lint::check(&program, lints)?;
+    if options.opt_level >= 1 {
+        fold::fold_program(&mut program);
+    }
+    dce::eliminate_dead_code(&mut program, lints)?;
+    if passes.dump && (options.emit.is_some() || options.target != Target::C) {
+        *pass_dumps = opt::dump_passes(&program, &passes);
+    }
+
+    // Dump an intermediate form instead of compiling.
+    match options.emit {
+        Some(EmitKind::Ir) => Ok(opt::lower(&program, &passes).to_string()),
+        Some(EmitKind::CfgDot) => Ok(opt::lower(&program, &passes).to_dot()),
+        Some(EmitKind::Regalloc) => {
+            let registers = match regalloc::RegisterFile::by_name(&options.arch) {
+                Some(r) => r,
+                None => abort!("Unknown architecture '{}'", options.arch),
+            };
+            let ir = opt::lower(&program, &passes);
+            let allocation = regalloc::allocate(&ir, registers);
+            Ok(format!("{}\n{}", allocation, ir))
+        }
+        _ => {
+            let mut emitter = match (options.target, &options.line_file) {
+                (Target::C, Some(file)) => Box::new(CEmitter::new().line_directives(file)),
+                (target, _) => target.emitter(passes),
+            };
+            emitter.program(&program);
+            let mut code = Vec::new();
+            if let Err(e) = emitter.write(&mut code) {
+                abort!("Could not write the output: {}", e);
+            }
+            // The emitters build their text as Strings, so it is UTF-8.
+            Ok(String::from_utf8_lossy(&code).into_owned())
+        }
+    }
+}
+
+pub mod ast;
+pub mod astdump;
+pub mod cst;
+pub mod diagnostic;
+pub mod format;
+pub mod ir;
+pub mod definite;
+pub mod lint;
+pub mod fold;
+pub mod dce;
+pub mod ssa;
+pub mod opt;
+pub mod liveness;
+pub mod regalloc;
+pub mod lex;
+pub mod parse;
+pub mod emit;
+pub mod token;
+pub mod cemitter;
+pub mod llvmemitter;
+pub mod watemitter;
+pub mod qbeemitter;
+pub mod rustemitter;
+pub mod jsemitter;
+pub mod pyemitter;
@@ -0,0 +1,287 @@
+// lint.rs
+// Named warnings. Each lint has a level that can be changed with -A, -W and
+// -D on the command line or with a `# ttrust: allow(name)` comment, and the
+// checks here look for the ones that don't belong to another pass.
+
+use crate::ast::{BinOp, Expr, NumberType, Printable, Program, Statement, UnaryOp};
+use crate::diagnostic::Diagnostic;
+use crate::fold::evaluate;
+use crate::ir::{Ir, Operand, Terminator};
+use crate::liveness::{self, Liveness, Value};
+use crate::token::Span;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Lint {
+    UnusedVariable,
+    UnusedAssignment,
+    UnusedLabel,
+    UnreachableCode,
+    EmptyLoop,
+    ConstantCondition,
+    FloatEquality,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+impl Lint {
+    pub const ALL: [Lint; 7] = [
+        Lint::UnusedVariable,
+        Lint::UnusedAssignment,
+        Lint::UnusedLabel,
+        Lint::UnreachableCode,
+        Lint::EmptyLoop,
+        Lint::ConstantCondition,
+        Lint::FloatEquality,
+    ];
+
+    /// The name used on the command line and in pragmas
+    pub fn name(&self) -> &'static str {
+        match self {
+            Lint::UnusedVariable => "unused-variable",
+            Lint::UnusedAssignment => "unused-assignment",
+            Lint::UnusedLabel => "unused-label",
+            Lint::UnreachableCode => "unreachable-code",
+            Lint::EmptyLoop => "empty-loop",
+            Lint::ConstantCondition => "constant-condition",
+            Lint::FloatEquality => "float-equality",
+        }
+    }
+
+    pub fn by_name(name: &str) -> Option<Lint> {
+        Lint::ALL.iter().copied().find(|l| l.name() == name)
+    }
+
+    fn default_level(&self) -> Level {
+        Level::Warn
+    }
+}
+
+/// The level of every lint
+pub struct Lints {
+    levels: BTreeMap<Lint, Level>,
+    /// What happens to lints at the warn level, changed with `-D warnings`
+    /// and `-A warnings`
+    warnings: Level,
+    /// The warnings reported so far
+    reported: RefCell<Vec<Diagnostic>>,
+}
+
+impl Default for Lints {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Lints {
+    pub fn new() -> Self {
+        Self {
+            levels: Lint::ALL.iter().map(|l| (*l, l.default_level())).collect(),
+            warnings: Level::Warn,
+            reported: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Set the level of a lint, or of all warnings for the name "warnings".
+    /// Returns false for an unknown name.
+    pub fn set(&mut self, name: &str, level: Level) -> bool {
+        if name == "warnings" {
+            self.warnings = level;
+            return true;
+        }
+        match Lint::by_name(name) {
+            Some(lint) => {
+                self.levels.insert(lint, level);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn level(&self, lint: Lint) -> Level {
+        match self.levels[&lint] {
+            Level::Warn => self.warnings,
+            level => level,
+        }
+    }
+
+    /// Warn about or reject a problem at `span`, depending on the level of
+    /// its lint
+    pub fn report(&self, lint: Lint, span: Span, message: &str) -> Result<(), Diagnostic> {
+        let message = format!("{} [{}]", message, lint.name());
+        match self.level(lint) {
+            Level::Allow => {}
+            Level::Warn => self.reported.borrow_mut().push(Diagnostic::warning(message).at(span)),
+            Level::Deny => return Err(Diagnostic::error(message).at(span)),
+        }
+        Ok(())
+    }
+
+    /// Take the warnings reported so far
+    pub fn take_warnings(&self) -> Vec<Diagnostic> {
+        self.reported.take()
+    }
+}
+
+/// Run the lints that look at the program as written
+pub fn check(program: &Program, lints: &Lints) -> Result<(), Diagnostic> {
+    check_statements(&program.statements, program.number_type, lints)?;
+
+    let mut read = BTreeSet::new();
+    read_variables(&program.statements, &mut read);
+    let mut first = BTreeMap::new();
+    first_assignments(&program.statements, &mut first);
+    for v in program.variables.iter() {
+        if !read.contains(v) {
+            lints.report(Lint::UnusedVariable, first[v], &format!("Variable '{}' is never read.", v))?;
+        }
+    }
+    for (span, v) in overwritten(program) {
+        if read.contains(&v) {
+            let message = format!("Variable '{}' is assigned a value that is never read.", v);
+            lints.report(Lint::UnusedAssignment, span, &message)?;
+        }
+    }
+    Ok(())
+}
+
+fn check_statements(statements: &[Statement], number: NumberType, lints: &Lints) -> Result<(), Diagnostic> {
+    for stmt in statements {
+        match stmt {
+            Statement::If { condition, body, .. } => {
+                check_condition(condition, number, lints)?;
+                check_statements(body, number, lints)?;
+            }
+            Statement::While { condition, body, .. } => {
+                check_condition(condition, number, lints)?;
+                if body.is_empty() {
+                    lints.report(Lint::EmptyLoop, stmt.span(), &format!("WHILE {} has an empty body.", condition))?;
+                }
+                check_statements(body, number, lints)?;
+            }
+            Statement::Let { value, .. } => check_expr(value, number, lints)?,
+            Statement::Print { value: Printable::Expr(e), .. } => check_expr(e, number, lints)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn check_condition(condition: &Expr, number: NumberType, lints: &Lints) -> Result<(), Diagnostic> {
+    if let Some(c) = constant(condition, number) {
+        let truth = if c != 0.0 { "true" } else { "false" };
+        lints.report(Lint::ConstantCondition, condition.span(), &format!("Condition '{}' is always {}.", condition, truth))?;
+    }
+    check_expr(condition, number, lints)
+}
+
+fn check_expr(e: &Expr, number: NumberType, lints: &Lints) -> Result<(), Diagnostic> {
+    match e {
+        Expr::Binary(op, l, r, _) => {
+            if matches!(op, BinOp::EqEq | BinOp::NotEq) {
+                // A whole number on one side suggests the other side is
+                // thought of as an integer, but it is a float.
+                let whole = |e: &Expr| matches!(e, Expr::Number(n, _) if !n.contains(['.', 'e']));
+                if (whole(l) && constant(r, number).is_none()) || (whole(r) && constant(l, number).is_none()) {
+                    lints.report(
+                        Lint::FloatEquality,
+                        e.span(),
+                        &format!("Comparison '{}' tests floats for exact equality.", e),
+                    )?;
+                }
+            }
+            check_expr(l, number, lints)?;
+            check_expr(r, number, lints)
+        }
+        Expr::Unary(_, inner, _) => check_expr(inner, number, lints),
+        _ => Ok(()),
+    }
+}
+
+/// The value of an expression without variables
+fn constant(e: &Expr, number: NumberType) -> Option<f64> {
+    match e {
+        Expr::Number(n, _) => number.parse(n),
+        Expr::Var(_, _) => None,
+        Expr::Unary(UnaryOp::Plus, inner, _) => constant(inner, number),
+        Expr::Unary(UnaryOp::Minus, inner, _) => constant(inner, number).map(|v| -v),
+        Expr::Binary(op, l, r, _) => Some(evaluate(*op, constant(l, number)?, constant(r, number)?, number)),
+    }
+}
+
+fn read_variables(statements: &[Statement], read: &mut BTreeSet<String>) {
+    for stmt in statements {
+        match stmt {
+            Statement::Let { value, .. } => expr_variables(value, read),
+            Statement::Print { value: Printable::Expr(e), .. } => expr_variables(e, read),
+            Statement::If { condition, body, .. } | Statement::While { condition, body, .. } => {
+                expr_variables(condition, read);
+                read_variables(body, read);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn expr_variables(e: &Expr, read: &mut BTreeSet<String>) {
+    match e {
+        Expr::Var(v, _) => {
+            read.insert(v.clone());
+        }
+        Expr::Unary(_, inner, _) => expr_variables(inner, read),
+        Expr::Binary(_, l, r, _) => {
+            expr_variables(l, read);
+            expr_variables(r, read);
+        }
+        Expr::Number(_, _) => {}
+    }
+}
+
+/// Where each variable is first assigned, by LET or INPUT
+fn first_assignments(statements: &[Statement], first: &mut BTreeMap<String, Span>) {
+    for stmt in statements {
+        match stmt {
+            Statement::Let { name, span, .. } | Statement::Input { name, span } => {
+                first.entry(name.clone()).or_insert(*span);
+            }
+            Statement::If { body, .. } | Statement::While { body, .. } => first_assignments(body, first),
+            _ => {}
+        }
+    }
+}
+
+/// Each LET whose value is always overwritten before it is read, with its
+/// variable, in source order
+fn overwritten(program: &Program) -> Vec<(Span, String)> {
+    let mut ir = Ir::new(program);
+    // Dead code gets its own warning.
+    ir.remove_unreachable();
+    let liveness = Liveness::new(&ir);
+    let mut dead = Vec::new();
+    for (b, block) in ir.blocks.iter().enumerate() {
+        let mut live = liveness.live_out[b].clone();
+        if let Terminator::Branch { condition: Operand::Var(v), .. } = &block.terminator {
+            live.insert(Value::Var(v.clone()));
+        }
+        for (i, instr) in block.instrs.iter().enumerate().rev() {
+            let (uses, def) = liveness::uses_defs(instr);
+            if let (Some(Value::Var(v)), Some(span)) = (&def, block.lets.get(&i)) {
+                if !live.contains(&Value::Var(v.clone())) {
+                    dead.push((*span, v.clone()));
+                }
+            }
+            if let Some(d) = def {
+                live.remove(&d);
+            }
+            live.extend(uses);
+        }
+    }
+    dead.sort_by_key(|(span, _)| span.start);
+    dead
+}
@@ -0,0 +1,188 @@
+// liveness.rs
+// Liveness analysis over the IR, and the live intervals that the register
+// allocator works from. Instructions are numbered in block order, with the
+// terminator of each block taking the position after its last instruction.
+
+use crate::ir::{Instr, Ir, Operand, Terminator};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// Something that needs a register or a stack slot
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Value {
+    Temp(usize),
+    Var(String),
+}
+
+impl Value {
+    fn of(o: &Operand) -> Option<Value> {
+        match o {
+            Operand::Temp(t) => Some(Value::Temp(*t)),
+            Operand::Var(v) => Some(Value::Var(v.clone())),
+            Operand::Const(_) => None,
+        }
+    }
+}
+
+/// The values read and written by an instruction
+pub fn uses_defs(instr: &Instr) -> (Vec<Value>, Option<Value>) {
+    let (uses, def): (Vec<&Operand>, Option<&Operand>) = match instr {
+        Instr::Copy { dest, src } | Instr::Neg { dest, src } => (vec![src], Some(dest)),
+        Instr::Binary { dest, left, right, .. } => (vec![left, right], Some(dest)),
+        Instr::Print { value, .. } => (vec![value], None),
+        Instr::PrintStr { .. } => (Vec::new(), None),
+        // scanf may leave the variable alone, so it is read as well.
+        Instr::Input(v) => {
+            let v = Value::Var(v.clone());
+            return (vec![v.clone()], Some(v));
+        }
+    };
+    (uses.into_iter().filter_map(Value::of).collect(), def.and_then(Value::of))
+}
+
+fn terminator_uses(t: &Terminator) -> Vec<Value> {
+    match t {
+        Terminator::Branch { condition, .. } => Value::of(condition).into_iter().collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Whether an instruction calls into the C library, which clobbers the
+/// caller-saved registers
+pub fn is_call(instr: &Instr) -> bool {
+    matches!(instr, Instr::Print { .. } | Instr::PrintStr { .. } | Instr::Input(_))
+}
+
+/// The values live on entry to and exit from every block
+pub struct Liveness {
+    pub live_in: Vec<BTreeSet<Value>>,
+    pub live_out: Vec<BTreeSet<Value>>,
+}
+
+impl Liveness {
+    pub fn new(ir: &Ir) -> Self {
+        let n = ir.blocks.len();
+        // What each block reads before writing, and what it writes.
+        let mut gen = vec![BTreeSet::new(); n];
+        let mut kill = vec![BTreeSet::new(); n];
+        for (b, block) in ir.blocks.iter().enumerate() {
+            for instr in block.instrs.iter() {
+                let (uses, def) = uses_defs(instr);
+                for u in uses {
+                    if !kill[b].contains(&u) {
+                        gen[b].insert(u);
+                    }
+                }
+                if let Some(d) = def {
+                    kill[b].insert(d);
+                }
+            }
+            for u in terminator_uses(&block.terminator) {
+                if !kill[b].contains(&u) {
+                    gen[b].insert(u);
+                }
+            }
+        }
+
+        let mut live_in = vec![BTreeSet::new(); n];
+        let mut live_out = vec![BTreeSet::new(); n];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for b in (0..n).rev() {
+                let mut out = BTreeSet::new();
+                for s in ir.blocks[b].terminator.successors() {
+                    out.extend(live_in[s].iter().cloned());
+                }
+                let mut inn: BTreeSet<Value> = out.difference(&kill[b]).cloned().collect();
+                inn.extend(gen[b].iter().cloned());
+                if inn != live_in[b] || out != live_out[b] {
+                    live_in[b] = inn;
+                    live_out[b] = out;
+                    changed = true;
+                }
+            }
+        }
+        Self { live_in, live_out }
+    }
+}
+
+/// The range of positions over which a value is live
+#[derive(Clone, Debug, PartialEq)]
+pub struct Interval {
+    pub value: Value,
+    pub start: usize,
+    pub end: usize,
+    /// Whether the value is live across a call
+    pub crosses_call: bool,
+}
+
+/// The position of the first instruction of every block. A position is
+/// left free after each terminator for values that are live out.
+pub fn block_positions(ir: &Ir) -> Vec<usize> {
+    let mut positions = Vec::with_capacity(ir.blocks.len());
+    let mut pos = 0;
+    for block in ir.blocks.iter() {
+        positions.push(pos);
+        pos += block.instrs.len() + 2;
+    }
+    positions
+}
+
+/// Live intervals for every value, ordered by start position. Holes in a
+/// value's lifetime are ignored, as in Poletto and Sarkar's linear scan.
+pub fn intervals(ir: &Ir, liveness: &Liveness) -> Vec<Interval> {
+    let positions = block_positions(ir);
+    let mut ranges: BTreeMap<Value, (usize, usize)> = BTreeMap::new();
+    let mut calls = Vec::new();
+    let mut mark = |live: &BTreeSet<Value>, pos: usize| {
+        for v in live.iter() {
+            let r = ranges.entry(v.clone()).or_insert((pos, pos));
+            r.0 = r.0.min(pos);
+            r.1 = r.1.max(pos);
+        }
+    };
+    for (b, block) in ir.blocks.iter().enumerate() {
+        let base = positions[b];
+        let mut live = liveness.live_out[b].clone();
+        let term = base + block.instrs.len();
+        // Values live out of the block stay live until after its end.
+        mark(&live, term + 1);
+        live.extend(terminator_uses(&block.terminator));
+        mark(&live, term);
+        for (i, instr) in block.instrs.iter().enumerate().rev() {
+            let pos = base + i;
+            let (uses, def) = uses_defs(instr);
+            if let Some(d) = def {
+                mark(&BTreeSet::from([d.clone()]), pos);
+                live.remove(&d);
+            }
+            live.extend(uses);
+            mark(&live, pos);
+            if is_call(instr) {
+                calls.push(pos);
+            }
+        }
+        mark(&liveness.live_in[b], base);
+    }
+    let mut intervals: Vec<Interval> = ranges
+        .into_iter()
+        .map(|(value, (start, end))| Interval {
+            crosses_call: calls.iter().any(|&c| start < c && c < end),
+            value,
+            start,
+            end,
+        })
+        .collect();
+    intervals.sort_by_key(|i| (i.start, i.end));
+    intervals
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Temp(t) => write!(f, "%t{}", t),
+            Value::Var(v) => write!(f, "{}", v),
+        }
+    }
+}
@@ -0,0 +1,298 @@
+// llvmemitter.rs
+// The LLVM IR emitter. Produces textual IR (.ll) with opaque pointers
+// that can be fed to llc, opt, or clang.
+
+use crate::ast::{BinOp, NumberType, Program};
+use crate::emit::Emitter;
+use crate::ir::{Instr, Operand, Terminator};
+use crate::opt::{self, Passes};
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+pub struct LlvmEmitter {
+    passes: Passes,
+    header: String,
+    code: String,
+    num_strings: usize,
+    num_values: usize,
+    num_inputs: usize,
+    /// The i1 produced by each temporary that holds a comparison, so that
+    /// branches can use it directly
+    comparisons: BTreeMap<usize, String>,
+    /// The LLVM type of every value, float or double
+    ty: &'static str,
+}
+
+impl LlvmEmitter {
+    pub fn new(passes: Passes) -> Self {
+        Self {
+            passes,
+            header: String::new(),
+            code: String::new(),
+            num_strings: 0,
+            num_values: 0,
+            num_inputs: 0,
+            comparisons: BTreeMap::new(),
+            ty: "float",
+        }
+    }
+
+    /// A fresh SSA value that doesn't correspond to an IR temporary
+    fn value(&mut self) -> String {
+        self.num_values += 1;
+        format!("%v{}", self.num_values)
+    }
+
+    /// Emit an instruction inside the current block
+    fn instr(&mut self, code: &str) {
+        self.emit("  ");
+        self.emit_line(code);
+    }
+
+    /// Add a NUL-terminated string constant and return its global name
+    fn string_constant(&mut self, text: &str) -> String {
+        let name = format!("@.str.{}", self.num_strings);
+        self.num_strings += 1;
+        self.header_line(&format!(
+            "{} = private unnamed_addr constant [{} x i8] c\"{}\\00\"",
+            name,
+            text.len() + 1,
+            escape(text)
+        ));
+        name
+    }
+
+    /// The value of an operand, loading variables as needed
+    fn operand(&mut self, o: &Operand) -> String {
+        match o {
+            Operand::Const(c) => float_constant(*c),
+            Operand::Var(v) => {
+                let t = self.value();
+                self.instr(&format!("{} = load {}, ptr %var.{}", t, self.ty, v));
+                t
+            }
+            Operand::Temp(t) => format!("%t{}", t),
+        }
+    }
+
+    /// Store a computed value into the destination of an instruction
+    fn assign(&mut self, dest: &Operand, code: &str) {
+        match dest {
+            Operand::Temp(t) => self.instr(&format!("%t{} = {}", t, code)),
+            Operand::Var(v) => {
+                let t = self.value();
+                self.instr(&format!("{} = {}", t, code));
+                self.instr(&format!("store {} {}, ptr %var.{}", self.ty, t, v));
+            }
+            Operand::Const(_) => unreachable!(),
+        }
+    }
+
+    fn instruction(&mut self, instr: &Instr) {
+        let ty = self.ty;
+        match instr {
+            Instr::PrintStr { text, newline } => {
+                let mut text = text.clone();
+                if *newline {
+                    text.push('\n');
+                }
+                let s = self.string_constant(&text);
+                let t = self.value();
+                self.instr(&format!("{} = call i32 (ptr, ...) @printf(ptr @.fmt.str, ptr {})", t, s));
+            }
+            Instr::Print { value, newline } => {
+                let v = self.operand(value);
+                // printf takes a double.
+                let d = if ty == "double" {
+                    v
+                }
+                else {
+                    let d = self.value();
+                    self.instr(&format!("{} = fpext float {} to double", d, v));
+                    d
+                };
+                let fmt = if *newline { "@.fmt.numln" } else { "@.fmt.num" };
+                let t = self.value();
+                self.instr(&format!("{} = call i32 (ptr, ...) @printf(ptr {}, double {})", t, fmt, d));
+            }
+            Instr::Copy { dest, src } => {
+                let v = self.operand(src);
+                match dest {
+                    Operand::Var(name) => self.instr(&format!("store {} {}, ptr %var.{}", ty, v, name)),
+                    // LLVM has no copy instruction, and adding zero would turn
+                    // -0 into 0.
+                    _ => self.assign(dest, &format!("select i1 true, {} {}, {} {}", ty, v, ty, v)),
+                }
+            }
+            Instr::Neg { dest, src } => {
+                let v = self.operand(src);
+                self.assign(dest, &format!("fneg {} {}", ty, v));
+            }
+            Instr::Binary { dest, op, left, right } if op.is_comparison() => {
+                let a = self.operand(left);
+                let b = self.operand(right);
+                let pred = match op {
+                    BinOp::EqEq => "oeq",
+                    BinOp::NotEq => "une",
+                    BinOp::Lt => "olt",
+                    BinOp::LtEq => "ole",
+                    BinOp::Gt => "ogt",
+                    _ => "oge",
+                };
+                let c = self.value();
+                self.instr(&format!("{} = fcmp {} {} {}, {}", c, pred, ty, a, b));
+                if let Operand::Temp(t) = dest {
+                    self.comparisons.insert(*t, c.clone());
+                }
+                self.assign(dest, &format!("uitofp i1 {} to {}", c, ty));
+            }
+            Instr::Binary { dest, op, left, right } => {
+                let a = self.operand(left);
+                let b = self.operand(right);
+                let inst = match op {
+                    BinOp::Add => "fadd",
+                    BinOp::Sub => "fsub",
+                    BinOp::Mul => "fmul",
+                    _ => "fdiv",
+                };
+                let r = self.value();
+                self.instr(&format!("{} = {} {} {}, {}", r, inst, ty, a, b));
+                // LLVM folds constant arithmetic like 0 / 0 to a positive NaN
+                // where x86 produces a negative one. Sign NaN the way x86
+                // does: a NaN operand passes through and a new NaN is
+                // negative.
+                let mut nan = String::from(NEGATIVE_NAN);
+                for operand in [&b, &a] {
+                    let is_nan = self.value();
+                    self.instr(&format!("{} = fcmp uno {} {}, {}", is_nan, ty, operand, operand));
+                    let v = self.value();
+                    self.instr(&format!("{} = select i1 {}, {} {}, {} {}", v, is_nan, ty, operand, ty, nan));
+                    nan = v;
+                }
+                let is_nan = self.value();
+                self.instr(&format!("{} = fcmp uno {} {}, {}", is_nan, ty, r, r));
+                self.assign(dest, &format!("select i1 {}, {} {}, {} {}", is_nan, ty, nan, ty, r));
+            }
+            Instr::Input(name) => {
+                self.num_inputs += 1;
+                let id = self.num_inputs;
+                let r = self.value();
+                self.instr(&format!("{} = call i32 (ptr, ...) @scanf(ptr @.fmt.scan, ptr %var.{})", r, name));
+                let failed = self.value();
+                self.instr(&format!("{} = icmp eq i32 {}, 0", failed, r));
+                self.instr(&format!("br i1 {}, label %input.fail.{}, label %input.done.{}", failed, id, id));
+                self.emit_line(&format!("input.fail.{}:", id));
+                self.instr(&format!("store {} 0.0, ptr %var.{}", ty, name));
+                let t = self.value();
+                self.instr(&format!("{} = call i32 (ptr, ...) @scanf(ptr @.fmt.skip)", t));
+                self.instr(&format!("br label %input.done.{}", id));
+                self.emit_line(&format!("input.done.{}:", id));
+            }
+        }
+    }
+
+    fn terminator(&mut self, t: &Terminator) {
+        match t {
+            Terminator::Jump(b) => self.instr(&format!("br label %b{}", b)),
+            Terminator::Branch { condition, then, otherwise } => {
+                let c = match condition {
+                    Operand::Temp(t) if self.comparisons.contains_key(t) => self.comparisons[t].clone(),
+                    _ => {
+                        let v = self.operand(condition);
+                        let c = self.value();
+                        self.instr(&format!("{} = fcmp une {} {}, 0.0", c, self.ty, v));
+                        c
+                    }
+                };
+                self.instr(&format!("br i1 {}, label %b{}, label %b{}", c, then, otherwise));
+            }
+            Terminator::Return => self.instr("ret i32 0"),
+        }
+    }
+}
+
+/// The NaN that x86 arithmetic produces
+const NEGATIVE_NAN: &str = "0xFFF8000000000000";
+
+/// LLVM only accepts float constants that are exact, so spell them as the
+/// hexadecimal bit pattern of the equivalent double. That is also how
+/// double constants are written.
+fn float_constant(value: f64) -> String {
+    format!("0x{:016X}", value.to_bits())
+}
+
+/// Escape a string for use inside c"..."
+fn escape(text: &str) -> String {
+    let mut s = String::new();
+    for b in text.bytes() {
+        if b == b'"' || b == b'\\' || !(0x20..0x7f).contains(&b) {
+            s.push_str(&format!("\\{:02X}", b));
+        }
+        else {
+            s.push(b as char);
+        }
+    }
+    s
+}
+
+impl Emitter for LlvmEmitter {
+    fn emit(&mut self, code: &str) {
+        self.code.push_str(code);
+    }
+
+    fn emit_line(&mut self, code: &str) {
+        self.emit(code);
+        self.code.push('\n');
+    }
+
+    fn header_line(&mut self, code: &str) {
+        self.header.push_str(code);
+        self.header.push('\n');
+    }
+
+    fn write(&self, out: &mut dyn Write) -> io::Result<()> {
+        out.write_all(self.header.as_bytes())?;
+        out.write_all(self.code.as_bytes())
+    }
+
+    fn program(&mut self, program: &Program) {
+        let ir = opt::lower(program, &self.passes);
+        self.ty = match ir.number_type {
+            NumberType::F32 => "float",
+            NumberType::F64 => "double",
+        };
+        let scan = match ir.number_type {
+            NumberType::F32 => "%f",
+            NumberType::F64 => "%lf",
+        };
+        self.header_line("; ModuleID = 'ttrust'");
+        self.header_line("@.fmt.str = private unnamed_addr constant [3 x i8] c\"%s\\00\"");
+        self.header_line("@.fmt.num = private unnamed_addr constant [5 x i8] c\"%.2f\\00\"");
+        self.header_line("@.fmt.numln = private unnamed_addr constant [6 x i8] c\"%.2f\\0A\\00\"");
+        self.header_line(&format!(
+            "@.fmt.scan = private unnamed_addr constant [{} x i8] c\"{}\\00\"",
+            scan.len() + 1,
+            scan
+        ));
+        self.header_line("@.fmt.skip = private unnamed_addr constant [4 x i8] c\"%*s\\00\"");
+        self.emit_line("");
+        self.emit_line("declare i32 @printf(ptr, ...)");
+        self.emit_line("declare i32 @scanf(ptr, ...)");
+        self.emit_line("");
+        self.emit_line("define i32 @main() {");
+        self.emit_line("entry:");
+        for v in ir.variables.iter() {
+            self.instr(&format!("%var.{} = alloca {}", v, self.ty));
+            self.instr(&format!("store {} 0.0, ptr %var.{}", self.ty, v));
+        }
+        self.instr("br label %b0");
+        for (i, block) in ir.blocks.iter().enumerate() {
+            self.emit_line(&format!("b{}:", i));
+            for instr in block.instrs.iter() {
+                self.instruction(instr);
+            }
+            self.terminator(&block.terminator);
+        }
+        self.emit_line("}");
+    }
+}
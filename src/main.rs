@@ -1,7 +1,10 @@
-use std::io::prelude::*;
-use std::fs::File;
+use std::io::{self, prelude::*};
+use std::fs::{self, File};
 use std::env::args;
-use crate::emit::Emitter;
+use std::env::consts::EXE_SUFFIX;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+use ttrust::{compile_str, format, CompileOptions, Diagnostics, Dialect, EmitKind, Level, NumberType, Target};
 
 #[macro_export]
 macro_rules! abort
@@ -19,56 +22,346 @@ macro_rules! abort
             std::process::exit(-1)
 			});
 }
+
 fn main() {
     let arg_vec: Vec<_> = args().collect();
     if arg_vec.len() < 2 {
         abort!("Not enough arguments provided.");
     }
+    match arg_vec[1].as_str() {
+        "fmt" => return fmt_command(&arg_vec[2..]),
+        "build" => return build_command(&arg_vec[2..], false),
+        "run" => return build_command(&arg_vec[2..], true),
+        _ => {}
+    }
     // println!("Teeny tiny written in Rust.");
+    let (options, in_path, out_path) = parse_args(&arg_vec[1..]);
+    let code = compile_file(&in_path, &options);
+    write_output(&out_path.unwrap_or_else(|| String::from(options.default_path())), &code);
+}
+
+/// The switches shared by compiling, `ttrust build` and `ttrust run`.
+/// Returns the options, the input file and the -o path, if given.
+fn parse_args(args: &[String]) -> (CompileOptions, String, Option<String>) {
     let mut in_path = String::new();
-    let mut out_path = String::from("out.c");
-    let mut i = 1;
-    while i < arg_vec.len() {
-        let a = &arg_vec[i];
+    let mut out_path = None;
+    let mut options = CompileOptions::new();
+    let mut i = 0;
+    while i < args.len() {
+        let a = &args[i];
         if a.get(0..1) == Some("-") {
             match a.get(..).unwrap() {
                 "-o" => {
                     i += 1;
-                    if i >= arg_vec.len() {
+                    if i >= args.len() {
                         abort!("No output file specified to -o switch.");
                     }
-                    out_path = arg_vec[i].clone();    
+                    out_path = Some(args[i].clone());
+                }
+                _ if a.starts_with("--target=") => {
+                    options = match Target::by_name(&a["--target=".len()..]) {
+                        Some(t) => options.target(t),
+                        None => abort!("Unknown target '{}'", &a["--target=".len()..]),
+                    };
+                }
+                "-W" | "-A" | "-D" => {
+                    i += 1;
+                    if i >= args.len() {
+                        abort!("No lint name specified to {} switch.", a);
+                    }
+                    options = options.lint(&args[i], lint_level(a));
+                }
+                _ if a.starts_with("-W") || a.starts_with("-A") || a.starts_with("-D") => {
+                    options = options.lint(&a[2..], lint_level(&a[..2]));
+                }
+                "-O0" => options = options.opt_level(0),
+                "-O1" => options = options.opt_level(1),
+                "-O2" => options = options.opt_level(2),
+                _ if a.starts_with("-f") => options = options.pass(&a["-f".len()..]),
+                _ if a.starts_with("--arch=") => options = options.arch(&a["--arch=".len()..]),
+                _ if a.starts_with("--dialect=") => {
+                    options = match Dialect::by_name(&a["--dialect=".len()..]) {
+                        Some(d) => options.dialect(d),
+                        None => abort!("Unknown dialect '{}'", &a["--dialect=".len()..]),
+                    };
+                }
+                _ if a.starts_with("--number-type=") => {
+                    options = match NumberType::by_name(&a["--number-type=".len()..]) {
+                        Some(n) => options.number_type(n),
+                        None => abort!("Unknown number type '{}'", &a["--number-type=".len()..]),
+                    };
+                }
+                _ if a.starts_with("--emit=") => {
+                    options = match EmitKind::by_name(&a["--emit=".len()..]) {
+                        Some(kind) => options.emit(kind),
+                        None => abort!("Unknown --emit kind '{}'", &a["--emit=".len()..]),
+                    };
                 }
                 _ => abort!("Unknown switch {}", a),
             }
         }
         else {
-            if in_path.len() != 0 {
+            if !in_path.is_empty() {
                 abort!("Input file name specified more than once.");
             }
             in_path = a.clone();
         }
         i += 1;
     }
-    if in_path.len() < 1 {
+    if in_path.is_empty() {
         abort!("No filename specified.");
     }
-    let mut f = File::open(&in_path).expect("Unable to open file.");
+    (options, in_path, out_path)
+}
+
+/// Compile a file, printing any warnings. Exits on an error.
+fn compile_file(in_path: &str, options: &CompileOptions) -> String {
+    let mut f = File::open(in_path).expect("Unable to open file.");
     let mut input = String::new();
     let read_result = f.read_to_string(&mut input);
     if read_result.is_err() {
         abort!("Unable to read input file.");
     }
     // println!("Read {} bytes.", read_result.ok().unwrap());
-    let mut lexer = lex::Lexer::new(&input);
-    let mut emitter = cemitter::CEmitter::new(out_path);
-    let mut parser = parse::Parser::new(&mut lexer, &mut emitter);
-    parser.program();
-    emitter.write_file();
+    match compile_str(&input, options) {
+        Ok(output) => {
+            eprint!("{}", output.pass_dumps);
+            for w in output.warnings.iter() {
+                eprintln!("{}", w);
+            }
+            output.code
+        }
+        Err(diagnostics) => report(&diagnostics),
+    }
 }
 
-pub mod lex;
-pub mod parse;
-pub mod emit;
-pub mod token;
-pub mod cemitter;
\ No newline at end of file
+/// `ttrust build FILE` compiles to C and then to an executable with `$CC`
+/// (`cc` by default) and `$CFLAGS`. The executable is named after the file,
+/// or by -o. `ttrust run FILE` builds into a temporary directory and runs
+/// the result with this process's stdin and stdout, exiting with its exit
+/// code. Both take the same switches as compiling, except --target and
+/// --emit.
+fn build_command(args: &[String], run: bool) {
+    let command = if run { "run" } else { "build" };
+    if let Some(a) = args.iter().find(|a| a.starts_with("--target=") || a.starts_with("--emit=")) {
+        abort!("ttrust {} always compiles to C; {} can't be used with it", command, a);
+    }
+    let (options, in_path, out_path) = parse_args(args);
+    if run && out_path.is_some() {
+        abort!("ttrust run doesn't take -o");
+    }
+    // #line directives make the C compiler report errors against the
+    // Teeny source.
+    let code = compile_file(&in_path, &options.line_directives(&in_path));
+
+    let dir = match make_temp_dir() {
+        Ok(dir) => dir,
+        Err(e) => abort!("Could not create a temporary directory: {}", e),
+    };
+    let stem = Path::new(&in_path).file_stem().and_then(|s| s.to_str()).unwrap_or("out");
+    let c_path = dir.join(format!("{}.c", stem));
+    let exe_path = match out_path {
+        Some(path) => PathBuf::from(path),
+        None if run => dir.join(format!("{}{}", stem, EXE_SUFFIX)),
+        None => PathBuf::from(format!("{}{}", stem, EXE_SUFFIX)),
+    };
+    let status = fs::write(&c_path, &code).and_then(|_| cc(&c_path, &exe_path, &code));
+    let status = match status {
+        Ok(status) => status,
+        Err(e) => {
+            let _ = fs::remove_dir_all(&dir);
+            abort!("Could not run the C compiler: {}", e);
+        }
+    };
+    if !status.success() {
+        let _ = fs::remove_dir_all(&dir);
+        abort!("The C compiler failed on '{}'", in_path);
+    }
+    if !run {
+        let _ = fs::remove_dir_all(&dir);
+        return;
+    }
+
+    // A plain file name would be looked up on the PATH.
+    let exe_path = if exe_path.is_relative() { Path::new(".").join(exe_path) } else { exe_path };
+    let status = Command::new(&exe_path).status();
+    let _ = fs::remove_dir_all(&dir);
+    match status {
+        Ok(status) => std::process::exit(exit_code(status)),
+        Err(e) => abort!("Could not run '{}': {}", exe_path.display(), e),
+    }
+}
+
+/// A new directory that only this user can use, with a name that can't be
+/// guessed in advance. Creating it fails rather than reusing anything
+/// already there, so another user can't plant files in it.
+fn make_temp_dir() -> io::Result<PathBuf> {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+    let mut builder = fs::DirBuilder::new();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    let mut attempts = 0;
+    loop {
+        // RandomState is seeded randomly for each process and each call.
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u32(std::process::id());
+        if let Ok(time) = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH) {
+            hasher.write_u128(time.as_nanos());
+        }
+        let dir = std::env::temp_dir().join(format!("ttrust-{:016x}", hasher.finish()));
+        match builder.create(&dir) {
+            Ok(()) => return Ok(dir),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && attempts < 100 => attempts += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Compile C with `$CC`, which may include switches of its own, such as
+/// `CC="gcc -m32"`
+fn cc(c_path: &Path, exe_path: &Path, code: &str) -> io::Result<ExitStatus> {
+    let cc = std::env::var("CC").unwrap_or_default();
+    let cflags = std::env::var("CFLAGS").unwrap_or_default();
+    let mut words = cc.split_whitespace();
+    let program = words.next().unwrap_or("cc");
+    let mut command = Command::new(program);
+    command.args(words).args(cflags.split_whitespace());
+    command.arg("-o").arg(exe_path).arg(c_path);
+    // Libraries go after the source. Only math needs one.
+    if code.contains("#include <math.h>") {
+        command.arg("-lm");
+    }
+    command.status().map_err(|e| io::Error::new(e.kind(), format!("{}: {}", program, e)))
+}
+
+/// The exit code to pass on from a program. One killed by a signal
+/// exits the way a shell reports it, with 128 plus the signal number.
+fn exit_code(status: ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    status.code().unwrap_or(1)
+}
+
+/// The lint level a -W, -A or -D switch sets
+fn lint_level(switch: &str) -> Level {
+    match switch {
+        "-A" => Level::Allow,
+        "-W" => Level::Warn,
+        _ => Level::Deny,
+    }
+}
+
+/// Print the warnings and the error that stopped a compile, and exit
+fn report(diagnostics: &Diagnostics) -> ! {
+    for w in diagnostics.warnings.iter() {
+        eprintln!("{}", w);
+    }
+    println!("{}", diagnostics.error);
+    std::process::exit(-1)
+}
+
+/// `ttrust fmt [--check] [--dialect=...] FILE...` formats files in place.
+/// With --check nothing is written, and the exit code is 1 if any file
+/// would change.
+fn fmt_command(args: &[String]) {
+    let mut check = false;
+    let mut dialect = Dialect::Strict;
+    let mut paths = Vec::new();
+    for a in args {
+        match a.as_str() {
+            "--check" => check = true,
+            _ if a.starts_with("--dialect=") => {
+                dialect = match Dialect::by_name(&a["--dialect=".len()..]) {
+                    Some(d) => d,
+                    None => abort!("Unknown dialect '{}'", &a["--dialect=".len()..]),
+                };
+            }
+            _ if a.starts_with('-') => abort!("Unknown switch {}", a),
+            _ => paths.push(a),
+        }
+    }
+    if paths.is_empty() {
+        abort!("No filename specified.");
+    }
+    let mut unformatted = false;
+    for path in paths {
+        let source = match std::fs::read_to_string(path) {
+            Ok(s) => s,
+            Err(_) => abort!("Unable to read input file '{}'.", path),
+        };
+        let formatted = match format::format(&source, dialect) {
+            Ok(f) => f,
+            Err(error) => report(&Diagnostics { warnings: Vec::new(), error }),
+        };
+        if formatted == source {
+            continue;
+        }
+        if check {
+            println!("{} is not formatted", path);
+            unformatted = true;
+        }
+        else {
+            write_output(path, &formatted);
+        }
+    }
+    if unformatted {
+        std::process::exit(1);
+    }
+}
+
+/// Write the output to a file, or to stdout for "-". A file is written
+/// under a temporary name next to it and then renamed into place, so a
+/// failed write never leaves a truncated file behind.
+fn write_output(out_path: &str, text: &str) {
+    let result = if out_path == "-" {
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        out.write_all(text.as_bytes()).and_then(|_| out.flush())
+    }
+    else {
+        write_atomically(out_path, text.as_bytes())
+    };
+    match result {
+        Ok(()) => {}
+        // Errors normally go to stdout, but that is what failed.
+        Err(e) if out_path == "-" => {
+            eprintln!("Compiler error: Could not write to stdout: {}", e);
+            std::process::exit(-1)
+        }
+        Err(e) => abort!("Could not write '{}': {}", out_path, e),
+    }
+}
+
+fn write_atomically(out_path: &str, bytes: &[u8]) -> io::Result<()> {
+    // Something like /dev/null or a pipe has to be written where it is.
+    if matches!(fs::metadata(out_path), Ok(m) if !m.is_file()) {
+        let mut f = File::create(out_path)?;
+        return f.write_all(bytes).and_then(|_| f.flush());
+    }
+    let temp = format!("{}.{}.tmp", out_path, std::process::id());
+    let result = File::create(&temp)
+        .and_then(|mut f| {
+            f.write_all(bytes)?;
+            f.sync_all()?;
+            // A file rewritten in place, as by `ttrust fmt`, keeps its
+            // permissions.
+            if let Ok(metadata) = fs::metadata(out_path) {
+                f.set_permissions(metadata.permissions())?;
+            }
+            Ok(())
+        })
+        .and_then(|_| fs::rename(&temp, out_path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
@@ -0,0 +1,387 @@
+// opt.rs
+// Optimization passes over the IR in SSA form. Each pass can be turned on
+// or off with a -f flag, and -O2 turns them all on.
+
+use crate::ast::{BinOp, NumberType, Program};
+use crate::fold::evaluate;
+use crate::ir::{Instr, Ir, Operand, Terminator};
+use crate::ssa;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Which passes to run when lowering a program
+#[derive(Clone, Debug, Default)]
+pub struct Passes {
+    pub const_prop: bool,
+    pub copy_prop: bool,
+    pub cse: bool,
+    pub dse: bool,
+    /// Keep the IR from after every pass, see dump_passes
+    pub dump: bool,
+}
+
+impl Passes {
+    /// The passes for an optimization level
+    pub fn for_level(level: u8) -> Self {
+        let on = level >= 2;
+        Self {
+            const_prop: on,
+            copy_prop: on,
+            cse: on,
+            dse: on,
+            dump: false,
+        }
+    }
+
+    /// Handle -f<name> or -fno-<name>. Returns false for an unknown name.
+    pub fn set(&mut self, flag: &str) -> bool {
+        let (name, on) = match flag.strip_prefix("no-") {
+            Some(name) => (name, false),
+            None => (flag, true),
+        };
+        let pass = match name {
+            "const-prop" => &mut self.const_prop,
+            "copy-prop" => &mut self.copy_prop,
+            "cse" => &mut self.cse,
+            "dse" => &mut self.dse,
+            "dump-passes" => &mut self.dump,
+            _ => return false,
+        };
+        *pass = on;
+        true
+    }
+
+    fn any(&self) -> bool {
+        self.const_prop || self.copy_prop || self.cse || self.dse
+    }
+}
+
+/// Lower a program to IR and run the enabled passes over it
+pub fn lower(program: &Program, passes: &Passes) -> Ir {
+    run(program, passes, None)
+}
+
+/// The IR after SSA construction, after each enabled pass and after
+/// leaving SSA form, as `lower` would produce it
+pub fn dump_passes(program: &Program, passes: &Passes) -> String {
+    let mut out = String::new();
+    run(program, passes, Some(&mut out));
+    out
+}
+
+fn run(program: &Program, passes: &Passes, mut out: Option<&mut String>) -> Ir {
+    let mut ir = Ir::new(program);
+    if !passes.any() {
+        return ir;
+    }
+    let mut dump = |name: &str, ir: &Ir| {
+        if let Some(out) = out.as_mut() {
+            out.push_str(&format!("; after {}\n{}\n", name, ir));
+        }
+    };
+    ssa::construct(&mut ir);
+    dump("ssa", &ir);
+    if passes.const_prop {
+        const_prop(&mut ir);
+        dump("const-prop", &ir);
+    }
+    if passes.cse {
+        cse(&mut ir);
+        dump("cse", &ir);
+    }
+    if passes.copy_prop {
+        copy_prop(&mut ir);
+        dump("copy-prop", &ir);
+    }
+    if passes.dse {
+        dse(&mut ir);
+        dump("dse", &ir);
+    }
+    ssa::destruct(&mut ir);
+    dump("out-of-ssa", &ir);
+    ir
+}
+
+/// Every operand an instruction reads
+fn uses(instr: &Instr) -> Vec<&Operand> {
+    match instr {
+        Instr::Copy { src, .. } | Instr::Neg { src, .. } => vec![src],
+        Instr::Binary { left, right, .. } => vec![left, right],
+        Instr::Print { value, .. } => vec![value],
+        Instr::PrintStr { .. } | Instr::Input(_) => Vec::new(),
+    }
+}
+
+fn uses_mut(instr: &mut Instr) -> Vec<&mut Operand> {
+    match instr {
+        Instr::Copy { src, .. } | Instr::Neg { src, .. } => vec![src],
+        Instr::Binary { left, right, .. } => vec![left, right],
+        Instr::Print { value, .. } => vec![value],
+        Instr::PrintStr { .. } | Instr::Input(_) => Vec::new(),
+    }
+}
+
+/// The temporary an instruction defines
+fn defined_temp(instr: &Instr) -> Option<usize> {
+    match instr {
+        Instr::Copy { dest: Operand::Temp(t), .. }
+        | Instr::Neg { dest: Operand::Temp(t), .. }
+        | Instr::Binary { dest: Operand::Temp(t), .. } => Some(*t),
+        _ => None,
+    }
+}
+
+/// Replace temporaries throughout the IR, following chains of replacements
+fn substitute(ir: &mut Ir, subst: &BTreeMap<usize, Operand>) {
+    let resolve = |o: &mut Operand| {
+        while let Operand::Temp(t) = o {
+            match subst.get(t) {
+                Some(new) => *o = new.clone(),
+                None => break,
+            }
+        }
+    };
+    for block in ir.blocks.iter_mut() {
+        for phi in block.phis.iter_mut() {
+            for (_, o) in phi.args.iter_mut() {
+                resolve(o);
+            }
+        }
+        for instr in block.instrs.iter_mut() {
+            for o in uses_mut(instr) {
+                resolve(o);
+            }
+        }
+        if let Terminator::Branch { condition, .. } = &mut block.terminator {
+            resolve(condition);
+        }
+    }
+}
+
+/// The single value a phi merges, ignoring arguments that are the phi
+/// itself, if all of its arguments agree
+fn trivial_phi(dest: usize, args: &[(usize, Operand)]) -> Option<Operand> {
+    let mut value: Option<&Operand> = None;
+    for (_, o) in args.iter() {
+        if *o == Operand::Temp(dest) {
+            continue;
+        }
+        match value {
+            None => value = Some(o),
+            Some(v) if same(v, o) => {}
+            Some(_) => return None,
+        }
+    }
+    value.cloned()
+}
+
+/// Operand equality that treats constants by their bits, so that -0 and 0
+/// differ and NaN equals itself
+fn same(a: &Operand, b: &Operand) -> bool {
+    match (a, b) {
+        (Operand::Const(x), Operand::Const(y)) => x.to_bits() == y.to_bits(),
+        _ => a == b,
+    }
+}
+
+/// Propagate values into their uses until nothing changes. `propagate`
+/// decides which copies and trivial phis are replaced by their value.
+fn propagate(ir: &mut Ir, fold: bool, propagate: impl Fn(&Operand) -> bool) {
+    let number = ir.number_type;
+    loop {
+        let mut subst = BTreeMap::new();
+        for block in ir.blocks.iter_mut() {
+            block.phis.retain(|phi| match trivial_phi(phi.dest, &phi.args) {
+                Some(v) if propagate(&v) => {
+                    subst.insert(phi.dest, v);
+                    false
+                }
+                _ => true,
+            });
+            for instr in block.instrs.iter_mut() {
+                if fold {
+                    fold_instr(instr, number);
+                }
+            }
+            block.instrs.retain(|instr| match instr {
+                Instr::Copy { dest: Operand::Temp(t), src } if propagate(src) => {
+                    subst.insert(*t, src.clone());
+                    false
+                }
+                _ => true,
+            });
+        }
+        let mut changed = !subst.is_empty();
+        substitute(ir, &subst);
+        if fold {
+            changed |= fold_branches(ir);
+        }
+        if !changed {
+            break;
+        }
+    }
+}
+
+/// Turn arithmetic on constants into a copy of the result. Results that
+/// are NaN are left to run time, like in the AST folder.
+fn fold_instr(instr: &mut Instr, number: NumberType) {
+    let value = match instr {
+        Instr::Neg { src: Operand::Const(c), .. } => -*c,
+        Instr::Binary { op, left: Operand::Const(a), right: Operand::Const(b), .. } => evaluate(*op, *a, *b, number),
+        _ => return,
+    };
+    if value.is_nan() {
+        return;
+    }
+    if let Instr::Neg { dest, .. } | Instr::Binary { dest, .. } = instr {
+        *instr = Instr::Copy { dest: dest.clone(), src: Operand::Const(value) };
+    }
+}
+
+/// Turn branches on constants into jumps, dropping the blocks that can no
+/// longer be reached. Returns whether anything changed.
+fn fold_branches(ir: &mut Ir) -> bool {
+    let mut changed = false;
+    for b in 0..ir.blocks.len() {
+        if let Terminator::Branch { condition: Operand::Const(c), then, otherwise } = ir.blocks[b].terminator {
+            let (target, dropped) = if c != 0.0 { (then, otherwise) } else { (otherwise, then) };
+            ir.blocks[b].terminator = Terminator::Jump(target);
+            if dropped != target {
+                for phi in ir.blocks[dropped].phis.iter_mut() {
+                    phi.args.retain(|(p, _)| *p != b);
+                }
+            }
+            changed = true;
+        }
+    }
+    if changed {
+        ir.remove_unreachable();
+    }
+    changed
+}
+
+/// Constant propagation: fold arithmetic and branches on constants and
+/// replace temporaries that hold a constant with the constant.
+pub fn const_prop(ir: &mut Ir) {
+    propagate(ir, true, |o| matches!(o, Operand::Const(_)));
+}
+
+/// Copy propagation: replace temporaries that are copies of another
+/// temporary with that temporary. Copies out of variables stay, since
+/// INPUT changes them.
+pub fn copy_prop(ir: &mut Ir) {
+    propagate(ir, false, |o| matches!(o, Operand::Temp(_)));
+}
+
+/// Dead store elimination: remove computations whose result is never used.
+/// Printing, INPUT and stores to variables are always kept, along with
+/// whatever they depend on.
+pub fn dse(ir: &mut Ir) {
+    let mut defs = BTreeMap::new();
+    let mut live = BTreeSet::new();
+    let mut work = Vec::new();
+    fn mark(o: &Operand, work: &mut Vec<usize>) {
+        if let Operand::Temp(t) = o {
+            work.push(*t);
+        }
+    }
+    for (b, block) in ir.blocks.iter().enumerate() {
+        for (i, phi) in block.phis.iter().enumerate() {
+            defs.insert(phi.dest, (b, None, i));
+        }
+        for (i, instr) in block.instrs.iter().enumerate() {
+            match defined_temp(instr) {
+                Some(t) => {
+                    defs.insert(t, (b, Some(i), 0));
+                }
+                None => {
+                    for o in uses(instr) {
+                        mark(o, &mut work);
+                    }
+                }
+            }
+        }
+        if let Terminator::Branch { condition, .. } = &block.terminator {
+            mark(condition, &mut work);
+        }
+    }
+    while let Some(t) = work.pop() {
+        if !live.insert(t) {
+            continue;
+        }
+        let Some(&(b, instr, i)) = defs.get(&t) else { continue };
+        match instr {
+            Some(i) => {
+                for o in uses(&ir.blocks[b].instrs[i]) {
+                    mark(o, &mut work);
+                }
+            }
+            None => {
+                for (_, o) in ir.blocks[b].phis[i].args.iter() {
+                    mark(o, &mut work);
+                }
+            }
+        }
+    }
+    for block in ir.blocks.iter_mut() {
+        block.phis.retain(|phi| live.contains(&phi.dest));
+        block.instrs.retain(|instr| defined_temp(instr).is_none_or(|t| live.contains(&t)));
+    }
+}
+
+/// Common subexpression elimination: an operation already computed in a
+/// dominating block is replaced with a copy of the earlier result.
+pub fn cse(ir: &mut Ir) {
+    let idom = ssa::dominators(ir);
+    let children = ssa::dominator_tree(&idom);
+    cse_block(ir, 0, &children, &mut BTreeMap::new(), &mut BTreeMap::new());
+}
+
+/// A key that is equal for operations that always compute the same value.
+/// `replaced` maps temporaries already found to repeat an earlier one.
+fn expression_key(instr: &Instr, replaced: &BTreeMap<usize, usize>) -> Option<String> {
+    let key = |o: &Operand| match o {
+        Operand::Const(c) => format!("{:016x}", c.to_bits()),
+        Operand::Var(v) => format!("v{}", v),
+        Operand::Temp(t) => format!("t{}", replaced.get(t).unwrap_or(t)),
+    };
+    match instr {
+        Instr::Neg { dest: Operand::Temp(_), src: src @ (Operand::Const(_) | Operand::Temp(_)) } => {
+            Some(format!("neg {}", key(src)))
+        }
+        Instr::Binary { dest: Operand::Temp(_), op, left, right } => {
+            // Reading a variable isn't pure, since INPUT may change it.
+            if matches!(left, Operand::Var(_)) || matches!(right, Operand::Var(_)) {
+                return None;
+            }
+            let (mut a, mut b) = (key(left), key(right));
+            if matches!(op, BinOp::Add | BinOp::Mul | BinOp::EqEq | BinOp::NotEq) && a > b {
+                std::mem::swap(&mut a, &mut b);
+            }
+            Some(format!("{:?} {} {}", op, a, b))
+        }
+        _ => None,
+    }
+}
+
+fn cse_block(ir: &mut Ir, b: usize, children: &[Vec<usize>], available: &mut BTreeMap<String, usize>, replaced: &mut BTreeMap<usize, usize>) {
+    let mut added = Vec::new();
+    for instr in ir.blocks[b].instrs.iter_mut() {
+        let (Some(key), Some(t)) = (expression_key(instr, replaced), defined_temp(instr)) else { continue };
+        match available.get(&key) {
+            Some(&earlier) => {
+                *instr = Instr::Copy { dest: Operand::Temp(t), src: Operand::Temp(earlier) };
+                replaced.insert(t, earlier);
+            }
+            None => {
+                available.insert(key.clone(), t);
+                added.push(key);
+            }
+        }
+    }
+    for &c in children[b].iter() {
+        cse_block(ir, c, children, available, replaced);
+    }
+    for key in added {
+        available.remove(&key);
+    }
+}
@@ -1,34 +1,43 @@
-
+use crate::diagnostic::Diagnostic;
 use crate::lex::Lexer;
-use crate::emit::Emitter;
-use crate::token::{Token, TokenType};
-use std::collections::BTreeSet;
-pub struct Parser<'a, 'b> {
+use crate::ast::{BinOp, Expr, NumberType, Printable, Program, Statement, UnaryOp};
+use crate::token::{Span, Token, TokenType};
+use std::collections::{BTreeMap, BTreeSet};
+pub struct Parser<'a> {
     lexer: &'a mut Lexer,
-    emitter: &'b mut dyn Emitter,
     cur_token: Token,
     peek_token: Token,
+    /// Where the last token consumed ends
+    prev_end: usize,
     symbols: BTreeSet<String>,
+    variables: Vec<String>,
     labels_declared: BTreeSet<String>,
-    labels_gotoed: BTreeSet<String>,
+    /// Where the first GOTO to each label names it
+    labels_gotoed: BTreeMap<String, Span>,
 }
 
-impl<'a, 'b> Parser<'a, 'b> {
-    pub fn new(lexer: &'a mut Lexer, emitter: &'b mut dyn Emitter) -> Self {
+impl<'a> Parser<'a> {
+    pub fn new(lexer: &'a mut Lexer) -> Result<Self, Diagnostic> {
         let mut s = Self {
             lexer,
-            emitter,
             cur_token: Token::default(),
             peek_token: Token::default(),
+            prev_end: 0,
             symbols: BTreeSet::new(),
+            variables: Vec::new(),
             labels_declared: BTreeSet::new(),
-            labels_gotoed: BTreeSet::new(),
+            labels_gotoed: BTreeMap::new(),
         };
-        s.next_token();
-        s.next_token();
-        s
+        s.next_token()?;
+        s.next_token()?;
+        Ok(s)
     }
 
+    /// Where the current token starts, for error messages
+    fn location(&self) -> String {
+        let span = self.cur_token.span;
+        format!("line {}, column {}", span.line, span.column)
+    }
     /// Check if the current token matches
     fn check_token(&self, kind: TokenType) -> bool {
         kind == self.cur_token.kind
@@ -38,23 +47,57 @@ impl<'a, 'b> Parser<'a, 'b> {
         // kind == self.peek_token.kind
     // }
     /// Advance to next token (or give an error)
-    fn match_token(&mut self, kind: TokenType) {
+    fn match_token(&mut self, kind: TokenType) -> Result<(), Diagnostic> {
         if !self.check_token(kind) {
-            abort!("Expected {:?}, got {:?}", kind, self.cur_token.kind);
+            if self.misspelt_keyword() == Some(kind) {
+                abort!(
+                    "Expected {:?}, got '{}' at {}. Keywords are upper case: did you mean {}?",
+                    kind,
+                    self.cur_token.text,
+                    self.location(),
+                    self.cur_token.text.to_ascii_uppercase()
+                );
+            }
+            abort!("Expected {:?}, got {:?} at {}", kind, self.cur_token.kind, self.location());
+        }
+        self.next_token()
+    }
+    /// The keyword an identifier would be if it were upper case
+    fn misspelt_keyword(&self) -> Option<TokenType> {
+        if !self.check_token(TokenType::Ident) {
+            return None;
+        }
+        match Token::check_if_keyword(&self.cur_token.text.to_ascii_uppercase()) {
+            TokenType::Unknown => None,
+            keyword => Some(keyword),
         }
-        self.next_token();
     }
     /// Get the next token from the lexer
-    fn next_token(&mut self) {
+    fn next_token(&mut self) -> Result<(), Diagnostic> {
+        self.prev_end = self.cur_token.span.end;
         self.cur_token = self.peek_token.clone();
-        self.peek_token = self.lexer.get_token();
+        self.peek_token = self.lexer.get_token()?;
+        Ok(())
     }
 
     // See if we are looking at the comparison operator
-    fn is_comparison_operator(&self) -> bool {
+    fn comparison_operator(&self) -> Option<BinOp> {
         match self.cur_token.kind {
-            TokenType::Gt | TokenType::GtEq | TokenType::Lt | TokenType::LtEq | TokenType::EqEq | TokenType::NotEq => true,
-            _ => false
+            TokenType::Gt => Some(BinOp::Gt),
+            TokenType::GtEq => Some(BinOp::GtEq),
+            TokenType::Lt => Some(BinOp::Lt),
+            TokenType::LtEq => Some(BinOp::LtEq),
+            TokenType::EqEq => Some(BinOp::EqEq),
+            TokenType::NotEq => Some(BinOp::NotEq),
+            _ => None
+        }
+    }
+
+    /// Record a variable assignment, remembering the order we first saw it.
+    fn declare_variable(&mut self, name: &str) {
+        if !self.symbols.contains(name) {
+            self.symbols.insert(name.to_string());
+            self.variables.push(name.to_string());
         }
     }
 
@@ -64,229 +107,231 @@ impl<'a, 'b> Parser<'a, 'b> {
     // ////////////////////////
 
     /// The program itself token
-    pub fn program(&mut self) {
+    pub fn program(&mut self) -> Result<Program, Diagnostic> {
         // println!("PROGRAM");
-        self.emitter.header_line("#include <stdio.h>");
-        self.emitter.header_line("int main(void) {");
+        let mut statements = Vec::new();
         // Skip preceding newlines
         while self.check_token(TokenType::Newline) {
-            self.next_token();
+            self.next_token()?;
         }
         // We have some "meat", see what it is
         while !self.check_token(TokenType::Eof) {
-            self.statement();
+            statements.push(self.statement()?);
         }
 
-        self.emitter.emit_line("return 0;\n}");
-        for label in self.labels_gotoed.iter() {
+        for (label, span) in self.labels_gotoed.iter() {
             if !self.labels_declared.contains(label) {
-                abort!("Attempted to GOTO to undeclared label '{}'", label);
+                abort!("Attempted to GOTO to undeclared label '{}' at line {}, column {}", label, span.line, span.column);
             }
         }
+        Ok(Program {
+            statements,
+            variables: std::mem::take(&mut self.variables),
+            // Set by the caller; the syntax is the same either way.
+            number_type: NumberType::default(),
+        })
+    }
+
+    /// Statements up to (but not including) the given closing keyword
+    fn block(&mut self, end: TokenType) -> Result<Vec<Statement>, Diagnostic> {
+        let mut body = Vec::new();
+        while !self.check_token(end) {
+            body.push(self.statement()?);
+        }
+        self.match_token(end)?;
+        Ok(body)
+    }
+
+    /// A span from `start` to the end of the last token consumed
+    fn span_from(&self, start: Span) -> Span {
+        Span { end: self.prev_end, ..start }
     }
 
     /// A particular statement in a program
-    fn statement(&mut self) {
-        match self.cur_token.kind {
-            TokenType::Print => {
+    fn statement(&mut self) -> Result<Statement, Diagnostic> {
+        let start = self.cur_token.span;
+        let stmt = match self.cur_token.kind {
+            TokenType::Print | TokenType::PrintLn => {
                 // println!("STATEMENT-PRINT");
-                self.next_token();
-                if self.check_token(TokenType::String) {
-                    let mut s = String::new();
-                    s.push_str("printf(\"");
-                    s.push_str(&self.cur_token.text);
-                    s.push_str("\");");
-                    self.emitter.emit_line(&s);
-                    self.next_token();
+                let newline = self.check_token(TokenType::PrintLn);
+                self.next_token()?;
+                let value = if self.check_token(TokenType::String) {
+                    let text = self.cur_token.text.clone();
+                    self.next_token()?;
+                    Printable::String(text)
                 }
                 else {
-                    self.emitter.emit("printf(\"%.2f\", (float)(");
-                    self.expression();
-                    self.emitter.emit_line("));");
-                }
-            }
-            TokenType::PrintLn => {
-                // println!("STATEMENT-PRINT");
-                self.next_token();
-                if self.check_token(TokenType::String) {
-                    let mut s = String::new();
-                    s.push_str("printf(\"");
-                    s.push_str(&self.cur_token.text);
-                    s.push_str("\\n\");");
-                    self.emitter.emit_line(&s);
-                    self.next_token();
-                }
-                else {
-                    self.emitter.emit("printf(\"%.2f\\n\", (float)(");
-                    self.expression();
-                    self.emitter.emit_line("));");
-                }
+                    Printable::Expr(self.expression()?)
+                };
+                Statement::Print { value, newline, span: self.span_from(start) }
             }
             TokenType::If => {
                 // println!("STATEMENT-IF");
-                self.next_token();
-                self.emitter.emit("if (");
-                self.comparison();
-                self.match_token(TokenType::Then);
-                self.nl();
-                self.emitter.emit_line(") {");
-                while !self.check_token(TokenType::EndIf) {
-                    self.statement();
-                }
-                self.match_token(TokenType::EndIf);
-                self.emitter.emit_line("}");
+                self.next_token()?;
+                let condition = self.comparison()?;
+                self.match_token(TokenType::Then)?;
+                self.nl()?;
+                let body = self.block(TokenType::EndIf)?;
+                Statement::If { condition, body, span: self.span_from(start) }
             }
             TokenType::While => {
                 // println!("STATEMENT-WHILE");
-                self.next_token();
-                self.emitter.emit("while (");
-                self.comparison();
-                self.match_token(TokenType::Repeat);
-                self.nl();
-                self.emitter.emit_line(") {");
-                while !self.check_token(TokenType::EndWhile) {
-                    self.statement();
-                }  
-                self.match_token(TokenType::EndWhile);
-                self.emitter.emit_line("}");
+                self.next_token()?;
+                let condition = self.comparison()?;
+                self.match_token(TokenType::Repeat)?;
+                self.nl()?;
+                let body = self.block(TokenType::EndWhile)?;
+                Statement::While { condition, body, span: self.span_from(start) }
             }
-            TokenType::Label => {
-                // println!("STATEMENT-LABEL");
-                self.next_token();
-                if self.labels_declared.contains(&self.cur_token.text) {
-                    abort!("Label already declared '{}'", self.cur_token.text);
+            TokenType::Label => self.label(None)?,
+            TokenType::DocComment => {
+                let mut lines = Vec::new();
+                while self.check_token(TokenType::DocComment) {
+                    lines.push(self.cur_token.text.clone());
+                    self.next_token()?;
+                    self.nl()?;
+                }
+                // The lexer only makes doc comments of `##` lines before a
+                // LABEL.
+                if !self.check_token(TokenType::Label) {
+                    abort!("Doc comments (##) must be followed by a LABEL, got {:?} at {}", self.cur_token.kind, self.location());
                 }
-                let mut s = String::from(&self.cur_token.text);
-                s.push(':');
-                self.emitter.emit_line(&s);
-                self.match_token(TokenType::Ident);
+                self.label(Some(lines.join("\n")))?
             }
             TokenType::Goto => {
                 // println!("STATEMENT-GOTO");
-                self.next_token();
-                self.labels_gotoed.insert(self.cur_token.text.clone());
-                let mut s = String::from("goto ");
-                s.push_str(&self.cur_token.text);
-                s.push(';');
-                self.emitter.emit_line(&s);
-                self.match_token(TokenType::Ident);
+                self.next_token()?;
+                self.labels_gotoed.entry(self.cur_token.text.clone()).or_insert(self.cur_token.span);
+                let name = self.cur_token.text.clone();
+                self.match_token(TokenType::Ident)?;
+                Statement::Goto { name, span: self.span_from(start) }
             }
             TokenType::Let => {
                 // println!("STATEMENT-LET");
-                self.next_token();
-                if !self.symbols.contains(&self.cur_token.text) {
-                    self.symbols.insert(self.cur_token.text.clone());
-                    let mut s = String::from("float ");
-                    s.push_str(&self.cur_token.text);
-                    s.push(';');
-                    self.emitter.header_line(&s);
-                }
-                self.emitter.emit(&self.cur_token.text);
-                self.emitter.emit(" = ");
-                self.match_token(TokenType::Ident);
-                self.match_token(TokenType::Eq);
-                self.expression();
-                self.emitter.emit_line(";");
+                self.next_token()?;
+                let name = self.cur_token.text.clone();
+                self.declare_variable(&name);
+                self.match_token(TokenType::Ident)?;
+                self.match_token(TokenType::Eq)?;
+                let value = self.expression()?;
+                Statement::Let { name, value, span: self.span_from(start) }
             }
             TokenType::Input => {
                 // println!("STATEMENT-INPUT");
-                self.next_token();
-                if !self.symbols.contains(&self.cur_token.text) {
-                    self.symbols.insert(self.cur_token.text.clone());
-                    let mut s = String::from("float ");
-                    s.push_str(&self.cur_token.text);
-                    s.push(';');
-                    self.emitter.header_line(&s);
-                }
-                self.emitter.emit("if (0 == scanf(\"%f\", &");
-                self.emitter.emit(&self.cur_token.text);
-                self.emitter.emit_line(")) {");
-                self.emitter.emit(&self.cur_token.text);
-                self.emitter.emit_line(" = 0;");
-                self.emitter.emit_line("scanf(\"%*s\");");
-                self.emitter.emit_line("}");
-                self.match_token(TokenType::Ident);
+                self.next_token()?;
+                let name = self.cur_token.text.clone();
+                self.declare_variable(&name);
+                self.match_token(TokenType::Ident)?;
+                Statement::Input { name, span: self.span_from(start) }
             }
             _ => {
-                abort!("Invalid statement ({:?})", self.cur_token.kind);
+                if self.misspelt_keyword().is_some() {
+                    abort!(
+                        "Invalid statement '{}' at {}. Keywords are upper case: did you mean {}? (--dialect=case-insensitive accepts either)",
+                        self.cur_token.text,
+                        self.location(),
+                        self.cur_token.text.to_ascii_uppercase()
+                    );
+                }
+                abort!("Invalid statement ({:?}) at {}", self.cur_token.kind, self.location());
             }
+        };
+        self.nl()?;
+        Ok(stmt)
+    }
+
+    /// A LABEL statement
+    fn label(&mut self, doc: Option<String>) -> Result<Statement, Diagnostic> {
+        // println!("STATEMENT-LABEL");
+        let start = self.cur_token.span;
+        self.next_token()?;
+        if self.labels_declared.contains(&self.cur_token.text) {
+            abort!("Label already declared '{}' at {}", self.cur_token.text, self.location());
         }
-        self.nl();
+        self.labels_declared.insert(self.cur_token.text.clone());
+        let name = self.cur_token.text.clone();
+        self.match_token(TokenType::Ident)?;
+        Ok(Statement::Label { name, doc, span: self.span_from(start) })
     }
 
     /// A newline token in a statement
-    fn nl(&mut self) {
+    fn nl(&mut self) -> Result<(), Diagnostic> {
         // println!("NEWLINE");
-        self.match_token(TokenType::Newline);
+        self.match_token(TokenType::Newline)?;
         while self.check_token(TokenType::Newline) {
-            self.next_token();
+            self.next_token()?;
         }
+        Ok(())
     }
     /// An expression in a statement
-    fn expression(&mut self) {
+    fn expression(&mut self) -> Result<Expr, Diagnostic> {
         // println!("EXPRESSION");
-        self.term();
+        let start = self.cur_token.span;
+        let mut left = self.term()?;
 
         while self.check_token(TokenType::Plus) || self.check_token(TokenType::Minus) {
-            self.emitter.emit(&self.cur_token.text);
-            self.next_token();
-            self.term();
+            let op = if self.check_token(TokenType::Plus) { BinOp::Add } else { BinOp::Sub };
+            self.next_token()?;
+            let right = self.term()?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right), self.span_from(start));
         }
+        Ok(left)
     }
 
     /// A comparison operator
-    fn comparison(&mut self) {
+    fn comparison(&mut self) -> Result<Expr, Diagnostic> {
         // println!("COMPARISON");
-        self.expression();
+        let start = self.cur_token.span;
+        let mut left = self.expression()?;
 
-        if self.is_comparison_operator() {
-            self.emitter.emit(&self.cur_token.text);
-            self.next_token();
-            self.expression();
+        if self.comparison_operator().is_none() {
+            abort!("Expected comparison operator, got {:?} at {}", self.cur_token.kind, self.location());
         }
-        else {
-            abort!("Expected comparison operator at {}.", self.cur_token.text);
-        }
-        while self.is_comparison_operator() {
-            self.emitter.emit(&self.cur_token.text);
-            self.next_token();
-            self.expression();
+        while let Some(op) = self.comparison_operator() {
+            self.next_token()?;
+            let right = self.expression()?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right), self.span_from(start));
         }
+        Ok(left)
     }
-    fn term(&mut self) {
+    fn term(&mut self) -> Result<Expr, Diagnostic> {
         // println!("TERM");
-        self.unary();
+        let start = self.cur_token.span;
+        let mut left = self.unary()?;
 
         while self.check_token(TokenType::Asterisk) || self.check_token(TokenType::Slash) {
-            self.emitter.emit(&self.cur_token.text);
-            self.next_token();
-            self.unary();
+            let op = if self.check_token(TokenType::Asterisk) { BinOp::Mul } else { BinOp::Div };
+            self.next_token()?;
+            let right = self.unary()?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right), self.span_from(start));
         }
+        Ok(left)
     }
-    fn unary(&mut self) {
+    fn unary(&mut self) -> Result<Expr, Diagnostic> {
         // println!("UNARY");
         if self.check_token(TokenType::Plus) || self.check_token(TokenType::Minus) {
-            self.emitter.emit(&self.cur_token.text);
-            self.next_token();
+            let start = self.cur_token.span;
+            let op = if self.check_token(TokenType::Plus) { UnaryOp::Plus } else { UnaryOp::Minus };
+            self.next_token()?;
+            let operand = self.primary()?;
+            return Ok(Expr::Unary(op, Box::new(operand), self.span_from(start)));
         }
-        self.primary();
+        self.primary()
     }
-    fn primary(&mut self) {
+    fn primary(&mut self) -> Result<Expr, Diagnostic> {
         // println!("PRIMARY ({})", self.cur_token.text);
         if self.check_token(TokenType::Number) {
-            self.emitter.emit(&self.cur_token.text);
-            self.next_token();
+            let e = Expr::Number(self.cur_token.text.clone(), self.cur_token.span);
+            self.next_token()?;
+            Ok(e)
         }
         else if self.check_token(TokenType::Ident) {
-            if !self.symbols.contains(&self.cur_token.text) {
-                abort!("Referencing variable before assignment '{}'", self.cur_token.text);
-            }
-            self.emitter.emit(&self.cur_token.text);
-            self.next_token();
+            let e = Expr::Var(self.cur_token.text.clone(), self.cur_token.span);
+            self.next_token()?;
+            Ok(e)
         }
         else {
-            abort!("Unexpected token at '{}'", self.cur_token.text);
+            abort!("Unexpected token '{}' at {}", self.cur_token.text.escape_debug(), self.location());
         }
     }
 }
-
@@ -0,0 +1,312 @@
+// pyemitter.rs
+// The Python emitter. Produces a Python 3 script. In f32 programs every
+// operation is rounded to float precision so that the output matches the C
+// target.
+
+use crate::ast::{BinOp, NumberType, Program};
+use crate::emit::Emitter;
+use crate::ir::{Block, Instr, Operand, Terminator};
+use crate::opt::{self, Passes};
+use std::io::{self, Write};
+
+/// Support code shared by every generated program
+const RUNTIME: &str = r#"
+def teeny_div(a, b):
+    """Float division with C's behaviour for a zero divisor."""
+    if b == 0:
+        if a == 0 or math.isnan(a):
+            return -math.nan
+        return math.copysign(math.inf, a) * math.copysign(1.0, b)
+    return teeny_round(a / b)
+
+
+def teeny_format(x):
+    """Format a number like C's printf("%.2f", x)."""
+    if math.isnan(x):
+        return "-nan" if math.copysign(1.0, x) < 0 else "nan"
+    return "%.2f" % x
+
+
+class TeenyInput:
+    """Reads numbers the way C's scanf("%f") does. `read` is called whenever
+    more text is needed and returns a string, or "" or None at end of input."""
+
+    def __init__(self, read):
+        self.read = read
+        self.buf = ""
+        self.pos = 0
+        self.eof = False
+
+    def peek(self):
+        while self.pos >= len(self.buf) and not self.eof:
+            more = self.read()
+            if not more:
+                self.eof = True
+            else:
+                self.buf = self.buf[self.pos:] + more
+                self.pos = 0
+        return self.buf[self.pos] if self.pos < len(self.buf) else None
+
+    def advance(self):
+        c = self.peek()
+        self.pos += 1
+        return c
+
+    def digits(self, allowed="0123456789"):
+        text = ""
+        while self.peek() is not None and self.peek() in allowed:
+            text += self.advance()
+        return text
+
+    def word(self, letters):
+        """Whether the next letters are `letters`, ignoring case. Like glibc,
+        this consumes the first letter that doesn't match."""
+        for letter in letters:
+            c = self.advance()
+            if c is None or c.lower() != letter:
+                return False
+        return True
+
+    def exponent(self, letters):
+        """An exponent introduced by one of `letters`, as text. The letter is
+        consumed even if no digits follow, which leaves the exponent out."""
+        if self.peek() is None or self.peek() not in letters:
+            return ""
+        self.advance()
+        text = letters[0]
+        if self.peek() is not None and self.peek() in "+-":
+            text += self.advance()
+        digits = self.digits()
+        return text + digits if digits else ""
+
+    def skip(self):
+        """Skip what scanf("%*s") does after a failed read."""
+        while self.peek() is not None and self.peek() in " \t\n\v\f\r":
+            self.advance()
+        while self.peek() is not None and self.peek() not in " \t\n\v\f\r":
+            self.advance()
+        return 0.0
+
+    def read_num(self, old):
+        """The new value of `old` after
+            if (0 == scanf("%f", &old)) { old = 0; scanf("%*s"); }"""
+        while self.peek() is not None and self.peek() in " \t\n\v\f\r":
+            self.advance()
+        if self.peek() is None:
+            return old
+        sign = ""
+        if self.peek() in "+-":
+            sign = self.advance()
+        if self.peek() is not None and self.peek() in "iI":
+            if not self.word("inf"):
+                return self.skip()
+            if self.peek() is not None and self.peek() in "iI" and not self.word("inity"):
+                return self.skip()
+            return float(sign + "inf")
+        if self.peek() is not None and self.peek() in "nN":
+            if not self.word("nan"):
+                return self.skip()
+            return float(sign + "nan")
+        n = self.digits()
+        if n == "0" and self.peek() is not None and self.peek() in "xX":
+            self.advance()
+            n = self.digits("0123456789abcdefABCDEF")
+            if self.peek() == ".":
+                self.advance()
+                n += "." + self.digits("0123456789abcdefABCDEF")
+            if n == "":
+                return self.skip()
+            if n == ".":
+                return float(sign + "0")
+            try:
+                return teeny_round(float.fromhex(sign + "0x" + n + self.exponent("pP")))
+            except OverflowError:
+                return teeny_round(float(sign + "inf"))
+        if self.peek() == ".":
+            self.advance()
+            n += "." + self.digits()
+        if n.replace(".", ""):
+            return teeny_round(float(sign + n + self.exponent("eE")))
+        return self.skip()
+"#;
+
+/// The most blocks dispatched by one if/elif chain
+const MAX_CHAIN: usize = 16;
+
+pub struct PyEmitter {
+    passes: Passes,
+    header: String,
+    code: String,
+}
+
+impl PyEmitter {
+    pub fn new(passes: Passes) -> Self {
+        Self {
+            passes,
+            header: String::new(),
+            code: String::new(),
+        }
+    }
+
+    fn line(&mut self, indent: usize, code: &str) {
+        self.emit(&"    ".repeat(indent));
+        self.emit_line(code);
+    }
+
+    fn instruction(&mut self, indent: usize, instr: &Instr) {
+        match instr {
+            Instr::PrintStr { text, newline } => {
+                let mut text = text.clone();
+                if *newline {
+                    text.push('\n');
+                }
+                self.line(indent, &format!("write({})", string_literal(&text)));
+            }
+            Instr::Print { value, newline } => {
+                let nl = if *newline { " + \"\\n\"" } else { "" };
+                self.line(indent, &format!("write(teeny_format({}){})", operand(value), nl));
+            }
+            Instr::Copy { dest, src } => {
+                self.line(indent, &format!("{} = {}", operand(dest), operand(src)));
+            }
+            Instr::Neg { dest, src } => {
+                self.line(indent, &format!("{} = -{}", operand(dest), operand(src)));
+            }
+            Instr::Binary { dest, op, left, right } if op.is_comparison() => {
+                let c = format!("{} {} {}", operand(left), op.symbol(), operand(right));
+                self.line(indent, &format!("{} = float({})", operand(dest), c));
+            }
+            Instr::Binary { dest, op: BinOp::Div, left, right } => {
+                self.line(indent, &format!("{} = teeny_div({}, {})", operand(dest), operand(left), operand(right)));
+            }
+            Instr::Binary { dest, op, left, right } => {
+                let e = format!("{} {} {}", operand(left), op.symbol(), operand(right));
+                self.line(indent, &format!("{} = teeny_round({})", operand(dest), e));
+            }
+            Instr::Input(name) => {
+                self.line(indent, &format!("v_{} = teeny_input.read_num(v_{})", name, name));
+            }
+        }
+    }
+
+    /// Run whichever of `blocks` (numbered from `first`) `state` names.
+    /// CPython can't compile a long elif chain, so a long run of blocks is
+    /// split in two with `state < middle` until the chains are short.
+    fn dispatch(&mut self, blocks: &[Block], first: usize, indent: usize) {
+        if blocks.len() > MAX_CHAIN {
+            let half = blocks.len() / 2;
+            self.line(indent, &format!("if state < {}:", first + half));
+            self.dispatch(&blocks[..half], first, indent + 1);
+            self.line(indent, "else:");
+            self.dispatch(&blocks[half..], first + half, indent + 1);
+            return;
+        }
+        for (i, block) in blocks.iter().enumerate() {
+            let keyword = if i == 0 { "if" } else { "elif" };
+            self.line(indent, &format!("{} state == {}:", keyword, first + i));
+            for instr in block.instrs.iter() {
+                self.instruction(indent + 1, instr);
+            }
+            match &block.terminator {
+                Terminator::Jump(t) => self.line(indent + 1, &format!("state = {}", t)),
+                Terminator::Branch { condition, then, otherwise } => {
+                    let c = operand(condition);
+                    self.line(indent + 1, &format!("state = {} if {} != 0 else {}", then, c, otherwise));
+                }
+                Terminator::Return => self.line(indent + 1, "return"),
+            }
+        }
+    }
+}
+
+/// A double quoted Python string
+fn string_literal(text: &str) -> String {
+    let mut s = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => s.push_str("\\\""),
+            '\\' => s.push_str("\\\\"),
+            '\n' => s.push_str("\\n"),
+            c if (c as u32) < 0x20 => s.push_str(&format!("\\x{:02x}", c as u32)),
+            c => s.push(c),
+        }
+    }
+    s.push('"');
+    s
+}
+
+/// Render an operand. Constants are written as doubles, which every f32
+/// value is exactly.
+fn operand(o: &Operand) -> String {
+    match o {
+        Operand::Const(c) if c.is_nan() => String::from("math.nan"),
+        Operand::Const(c) if c.is_infinite() => {
+            String::from(if *c < 0.0 { "(-math.inf)" } else { "math.inf" })
+        }
+        Operand::Const(c) if c.is_sign_negative() => format!("({:?})", c),
+        Operand::Const(c) => format!("{:?}", c),
+        Operand::Var(v) => format!("v_{}", v),
+        Operand::Temp(t) => format!("t{}", t),
+    }
+}
+
+impl Emitter for PyEmitter {
+    fn emit(&mut self, code: &str) {
+        self.code.push_str(code);
+    }
+
+    fn emit_line(&mut self, code: &str) {
+        self.emit(code);
+        self.code.push('\n');
+    }
+
+    fn header_line(&mut self, code: &str) {
+        self.header.push_str(code);
+        self.header.push('\n');
+    }
+
+    fn write(&self, out: &mut dyn Write) -> io::Result<()> {
+        out.write_all(self.header.as_bytes())?;
+        out.write_all(self.code.as_bytes())
+    }
+
+    fn program(&mut self, program: &Program) {
+        let ir = opt::lower(program, &self.passes);
+        self.header_line("# Generated by ttrust.");
+        self.header_line("import ctypes");
+        self.header_line("import math");
+        self.header_line("import sys");
+        self.header_line("");
+        self.header_line("");
+        match ir.number_type {
+            NumberType::F32 => {
+                self.header_line("def teeny_round(x):");
+                self.header_line("    \"\"\"Round a double to the nearest float, like a C float assignment.\"\"\"");
+                self.header_line("    return ctypes.c_float(x).value");
+            }
+            NumberType::F64 => {
+                self.header_line("def teeny_round(x):");
+                self.header_line("    \"\"\"Numbers are doubles, which Python floats already are.\"\"\"");
+                self.header_line("    return x");
+            }
+        }
+        self.header_line("");
+        self.header_line(RUNTIME);
+
+        self.emit_line("");
+        self.emit_line("def run(read=sys.stdin.readline, write=sys.stdout.write):");
+        self.line(1, "\"\"\"Run the program. INPUT pulls text from `read` (see TeenyInput)");
+        self.line(1, "and PRINT hands strings to `write`.\"\"\"");
+        self.line(1, "teeny_input = TeenyInput(read)");
+        for v in ir.variables.iter() {
+            self.line(1, &format!("v_{} = 0.0", v));
+        }
+        self.line(1, "state = 0");
+        self.line(1, "while True:");
+        self.dispatch(&ir.blocks, 0, 2);
+        self.emit_line("");
+        self.emit_line("");
+        self.emit_line("if __name__ == \"__main__\":");
+        self.line(1, "run()");
+    }
+}
@@ -0,0 +1,238 @@
+// qbeemitter.rs
+// The QBE emitter. Produces QBE intermediate language (.ssa) that the qbe
+// tool turns into assembly. Variables are temporaries of class s or d,
+// for f32 and f64 programs; QBE takes care of building SSA form from them.
+
+use crate::ast::{BinOp, NumberType, Program};
+use crate::emit::Emitter;
+use crate::ir::{Instr, Operand, Terminator};
+use crate::opt::{self, Passes};
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+pub struct QbeEmitter {
+    passes: Passes,
+    header: String,
+    code: String,
+    num_strings: usize,
+    num_words: usize,
+    num_inputs: usize,
+    /// The w holding each temporary that is a comparison, so that branches
+    /// can use it directly
+    comparisons: BTreeMap<usize, String>,
+    number_type: NumberType,
+}
+
+impl QbeEmitter {
+    pub fn new(passes: Passes) -> Self {
+        Self {
+            passes,
+            header: String::new(),
+            code: String::new(),
+            num_strings: 0,
+            num_words: 0,
+            num_inputs: 0,
+            comparisons: BTreeMap::new(),
+            number_type: NumberType::F32,
+        }
+    }
+
+    /// The class of every value, s or d
+    fn class(&self) -> char {
+        match self.number_type {
+            NumberType::F32 => 's',
+            NumberType::F64 => 'd',
+        }
+    }
+
+    /// A fresh w temporary that doesn't correspond to an IR temporary
+    fn word(&mut self) -> String {
+        self.num_words += 1;
+        format!("%w{}", self.num_words)
+    }
+
+    fn operand(&self, o: &Operand) -> String {
+        match o {
+            Operand::Const(c) => format!("{}_{}", self.class(), self.number_type.text(*c)),
+            Operand::Var(v) => format!("%v_{}", v),
+            Operand::Temp(t) => format!("%t{}", t),
+        }
+    }
+
+    fn instr(&mut self, code: &str) {
+        self.emit("    ");
+        self.emit_line(code);
+    }
+
+    /// Add a NUL-terminated data definition and return its name
+    fn string_constant(&mut self, text: &str) -> String {
+        let name = format!("$str{}", self.num_strings);
+        self.num_strings += 1;
+        let mut items = Vec::new();
+        let mut run = String::new();
+        for b in text.bytes() {
+            if b == b'"' || b == b'\\' || !(0x20..0x7f).contains(&b) {
+                if !run.is_empty() {
+                    items.push(format!("b \"{}\"", run));
+                    run.clear();
+                }
+                items.push(format!("b {}", b));
+            }
+            else {
+                run.push(b as char);
+            }
+        }
+        if !run.is_empty() {
+            items.push(format!("b \"{}\"", run));
+        }
+        items.push(String::from("b 0"));
+        self.header_line(&format!("data {} = {{ {} }}", name, items.join(", ")));
+        name
+    }
+
+    fn instruction(&mut self, instr: &Instr) {
+        let class = self.class();
+        match instr {
+            Instr::PrintStr { text, newline } => {
+                let mut text = text.clone();
+                if *newline {
+                    text.push('\n');
+                }
+                let s = self.string_constant(&text);
+                self.instr(&format!("call $printf(l $fmt_str, ..., l {})", s));
+            }
+            Instr::Print { value, newline } => {
+                let fmt = if *newline { "$fmt_numln" } else { "$fmt_num" };
+                // printf takes a double.
+                let mut value = self.operand(value);
+                if class == 's' {
+                    self.num_words += 1;
+                    let d = format!("%d{}", self.num_words);
+                    self.instr(&format!("{} =d exts {}", d, value));
+                    value = d;
+                }
+                self.instr(&format!("call $printf(l {}, ..., d {})", fmt, value));
+            }
+            Instr::Copy { dest, src } => {
+                self.instr(&format!("{} ={} copy {}", self.operand(dest), class, self.operand(src)));
+            }
+            Instr::Neg { dest, src } => {
+                self.instr(&format!("{} ={} neg {}", self.operand(dest), class, self.operand(src)));
+            }
+            Instr::Binary { dest, op, left, right } if op.is_comparison() => {
+                let inst = match op {
+                    BinOp::EqEq => "ceq",
+                    BinOp::NotEq => "cne",
+                    BinOp::Lt => "clt",
+                    BinOp::LtEq => "cle",
+                    BinOp::Gt => "cgt",
+                    _ => "cge",
+                };
+                let c = self.word();
+                let (left, right) = (self.operand(left), self.operand(right));
+                self.instr(&format!("{} =w {}{} {}, {}", c, inst, class, left, right));
+                if let Operand::Temp(t) = dest {
+                    self.comparisons.insert(*t, c.clone());
+                }
+                self.instr(&format!("{} ={} swtof {}", self.operand(dest), class, c));
+            }
+            Instr::Binary { dest, op, left, right } => {
+                let inst = match op {
+                    BinOp::Add => "add",
+                    BinOp::Sub => "sub",
+                    BinOp::Mul => "mul",
+                    _ => "div",
+                };
+                let (left, right) = (self.operand(left), self.operand(right));
+                self.instr(&format!("{} ={} {} {}, {}", self.operand(dest), class, inst, left, right));
+            }
+            Instr::Input(name) => {
+                // scanf needs an address, so go through the stack slot and
+                // keep the old value if we hit end of file.
+                self.num_inputs += 1;
+                let id = self.num_inputs;
+                self.instr(&format!("store{} %v_{}, %input", class, name));
+                let r = self.word();
+                self.instr(&format!("{} =w call $scanf(l $fmt_scan, ..., l %input)", r));
+                self.instr(&format!("%v_{} ={} load{} %input", name, class, class));
+                let failed = self.word();
+                self.instr(&format!("{} =w ceqw {}, 0", failed, r));
+                self.instr(&format!("jnz {}, @input_fail_{}, @input_done_{}", failed, id, id));
+                self.emit_line(&format!("@input_fail_{}", id));
+                self.instr(&format!("%v_{} ={} copy {}_0", name, class, class));
+                self.instr("call $scanf(l $fmt_skip, ...)");
+                self.emit_line(&format!("@input_done_{}", id));
+            }
+        }
+    }
+
+    fn terminator(&mut self, t: &Terminator) {
+        match t {
+            Terminator::Jump(b) => self.instr(&format!("jmp @b{}", b)),
+            Terminator::Branch { condition, then, otherwise } => {
+                let c = match condition {
+                    Operand::Temp(t) if self.comparisons.contains_key(t) => self.comparisons[t].clone(),
+                    _ => {
+                        let c = self.word();
+                        let class = self.class();
+                        self.instr(&format!("{} =w cne{} {}, {}_0", c, class, self.operand(condition), class));
+                        c
+                    }
+                };
+                self.instr(&format!("jnz {}, @b{}, @b{}", c, then, otherwise));
+            }
+            Terminator::Return => self.instr("ret 0"),
+        }
+    }
+}
+
+impl Emitter for QbeEmitter {
+    fn emit(&mut self, code: &str) {
+        self.code.push_str(code);
+    }
+
+    fn emit_line(&mut self, code: &str) {
+        self.emit(code);
+        self.code.push('\n');
+    }
+
+    fn header_line(&mut self, code: &str) {
+        self.header.push_str(code);
+        self.header.push('\n');
+    }
+
+    fn write(&self, out: &mut dyn Write) -> io::Result<()> {
+        out.write_all(self.header.as_bytes())?;
+        out.write_all(self.code.as_bytes())
+    }
+
+    fn program(&mut self, program: &Program) {
+        let ir = opt::lower(program, &self.passes);
+        self.number_type = ir.number_type;
+        let scan = match self.number_type {
+            NumberType::F32 => "%f",
+            NumberType::F64 => "%lf",
+        };
+        self.header_line("data $fmt_str = { b \"%s\", b 0 }");
+        self.header_line("data $fmt_num = { b \"%.2f\", b 0 }");
+        self.header_line("data $fmt_numln = { b \"%.2f\", b 10, b 0 }");
+        self.header_line(&format!("data $fmt_scan = {{ b \"{}\", b 0 }}", scan));
+        self.header_line("data $fmt_skip = { b \"%*s\", b 0 }");
+        self.emit_line("");
+        self.emit_line("export function w $main() {");
+        self.emit_line("@start");
+        self.instr("%input =l alloc8 8");
+        for v in ir.variables.iter() {
+            let class = self.class();
+            self.instr(&format!("%v_{} ={} copy {}_0", v, class, class));
+        }
+        for (i, block) in ir.blocks.iter().enumerate() {
+            self.emit_line(&format!("@b{}", i));
+            for instr in block.instrs.iter() {
+                self.instruction(instr);
+            }
+            self.terminator(&block.terminator);
+        }
+        self.emit_line("}");
+    }
+}
@@ -0,0 +1,213 @@
+// regalloc.rs
+// Linear-scan register allocation over the IR (Poletto and Sarkar). The
+// registers come from a description of the target machine, so any native
+// backend can share the allocator.
+
+use crate::ir::{Instr, Ir};
+use crate::liveness::{self, Interval, Liveness, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// The floating point registers a target machine offers for Teeny values
+pub struct RegisterFile {
+    pub name: &'static str,
+    /// Registers that calls into the C library may overwrite
+    pub caller_saved: &'static [&'static str],
+    /// Registers that survive calls
+    pub callee_saved: &'static [&'static str],
+}
+
+/// x86-64 System V. xmm0 and xmm1 are kept back for passing arguments to
+/// printf and for reloading spilled values, and no xmm register survives a
+/// call.
+pub const X86_64: RegisterFile = RegisterFile {
+    name: "x86-64",
+    caller_saved: &[
+        "xmm2", "xmm3", "xmm4", "xmm5", "xmm6", "xmm7", "xmm8", "xmm9", "xmm10", "xmm11", "xmm12", "xmm13", "xmm14",
+        "xmm15",
+    ],
+    callee_saved: &[],
+};
+
+/// RISC-V with the F extension (lp64f or lp64d). fa0 is kept back for
+/// arguments and ft0 and ft1 for reloading spilled values.
+pub const RISCV64: RegisterFile = RegisterFile {
+    name: "riscv64",
+    caller_saved: &["ft2", "ft3", "ft4", "ft5", "ft6", "ft7", "ft8", "ft9", "ft10", "ft11"],
+    callee_saved: &["fs0", "fs1", "fs2", "fs3", "fs4", "fs5", "fs6", "fs7", "fs8", "fs9", "fs10", "fs11"],
+};
+
+impl RegisterFile {
+    /// Look a register description up by the name used on the command line
+    pub fn by_name(name: &str) -> Option<&'static RegisterFile> {
+        match name {
+            "x86-64" => Some(&X86_64),
+            "riscv64" => Some(&RISCV64),
+            _ => None,
+        }
+    }
+}
+
+/// Where a value lives
+#[derive(Clone, Debug, PartialEq)]
+pub enum Location {
+    Register(&'static str),
+    /// Offset in bytes into the stack frame
+    Stack(usize),
+}
+
+/// The result of register allocation
+pub struct Allocation {
+    pub target: &'static str,
+    pub intervals: Vec<Interval>,
+    pub locations: BTreeMap<Value, Location>,
+    /// Bytes of stack needed for spilled values
+    pub frame_size: usize,
+}
+
+impl Allocation {
+    pub fn location(&self, value: &Value) -> &Location {
+        &self.locations[value]
+    }
+}
+
+struct Scan<'a> {
+    registers: &'a RegisterFile,
+    free: BTreeSet<&'static str>,
+    /// Intervals holding a register, with their register
+    active: Vec<(Interval, &'static str)>,
+    locations: BTreeMap<Value, Location>,
+    /// Bytes per stack slot, the size of the IR's numbers
+    slot_size: usize,
+    frame_size: usize,
+}
+
+impl Scan<'_> {
+    fn spill(&mut self, value: Value) {
+        self.locations.insert(value, Location::Stack(self.frame_size));
+        self.frame_size += self.slot_size;
+    }
+
+    fn usable(&self, interval: &Interval, reg: &str) -> bool {
+        !interval.crosses_call || self.registers.callee_saved.contains(&reg)
+    }
+
+    /// Release the registers of intervals that end before `start`
+    fn expire(&mut self, start: usize) {
+        let (done, active): (Vec<_>, Vec<_>) = self.active.drain(..).partition(|(i, _)| i.end < start);
+        self.active = active;
+        for (_, reg) in done {
+            self.free.insert(reg);
+        }
+    }
+
+    fn allocate(&mut self, interval: Interval) {
+        // Prefer registers that calls clobber, keeping the others for
+        // values that need them.
+        let choice = self
+            .registers
+            .caller_saved
+            .iter()
+            .chain(self.registers.callee_saved.iter())
+            .copied()
+            .find(|r| self.free.contains(r) && self.usable(&interval, r));
+        if let Some(reg) = choice {
+            self.free.remove(reg);
+            self.locations.insert(interval.value.clone(), Location::Register(reg));
+            self.active.push((interval, reg));
+            return;
+        }
+        // Otherwise take the register of whichever usable interval ends
+        // last, if that is later than this one.
+        let victim = self
+            .active
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, reg))| self.usable(&interval, reg))
+            .max_by_key(|(_, (i, _))| i.end)
+            .map(|(n, _)| n);
+        match victim {
+            Some(n) if self.active[n].0.end > interval.end => {
+                let (spilled, reg) = self.active.remove(n);
+                self.spill(spilled.value);
+                self.locations.insert(interval.value.clone(), Location::Register(reg));
+                self.active.push((interval, reg));
+            }
+            _ => self.spill(interval.value),
+        }
+    }
+}
+
+/// Assign every value in the IR a register or a stack slot
+pub fn allocate(ir: &Ir, registers: &'static RegisterFile) -> Allocation {
+    let liveness = Liveness::new(ir);
+    let intervals = liveness::intervals(ir, &liveness);
+
+    // scanf needs the address of the variable it reads into.
+    let mut in_memory = BTreeSet::new();
+    for block in ir.blocks.iter() {
+        for instr in block.instrs.iter() {
+            if let Instr::Input(v) = instr {
+                in_memory.insert(Value::Var(v.clone()));
+            }
+        }
+    }
+
+    let mut scan = Scan {
+        registers,
+        free: registers.caller_saved.iter().chain(registers.callee_saved.iter()).copied().collect(),
+        active: Vec::new(),
+        locations: BTreeMap::new(),
+        slot_size: ir.number_type.size(),
+        frame_size: 0,
+    };
+    for interval in intervals.iter() {
+        scan.expire(interval.start);
+        if in_memory.contains(&interval.value) {
+            scan.spill(interval.value.clone());
+        }
+        else {
+            scan.allocate(interval.clone());
+        }
+    }
+    Allocation {
+        target: registers.name,
+        intervals,
+        locations: scan.locations,
+        frame_size: scan.frame_size,
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Location::Register(r) => write!(f, "{}", r),
+            Location::Stack(offset) => write!(f, "[sp+{}]", offset),
+        }
+    }
+}
+
+impl fmt::Display for Allocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let registers: BTreeSet<_> = self
+            .locations
+            .values()
+            .filter_map(|l| match l {
+                Location::Register(r) => Some(r),
+                Location::Stack(_) => None,
+            })
+            .collect();
+        writeln!(
+            f,
+            "; {}: {} registers, {} bytes of stack",
+            self.target,
+            registers.len(),
+            self.frame_size
+        )?;
+        for i in self.intervals.iter() {
+            let call = if i.crosses_call { "  (across call)" } else { "" };
+            writeln!(f, "{:<8} [{}, {}]  {}{}", i.value.to_string(), i.start, i.end, self.location(&i.value), call)?;
+        }
+        Ok(())
+    }
+}
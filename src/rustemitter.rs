@@ -0,0 +1,388 @@
+// rustemitter.rs
+// The Rust emitter. Produces a standalone .rs file whose `run` function can
+// also be pulled into another crate. Rust has no goto, so the program runs
+// as a state machine over the basic blocks of the IR.
+
+use crate::ast::{NumberType, Program};
+use crate::emit::Emitter;
+use crate::ir::{Instr, Operand, Terminator};
+use crate::opt::{self, Passes};
+use std::io::{self, Write};
+
+/// Support code shared by every generated program
+const RUNTIME: &str = r#"
+/// Format a number like C's printf("%.2f", x)
+#[allow(dead_code)]
+fn teeny_format(x: Num) -> String {
+    if x.is_nan() {
+        String::from(if x.is_sign_negative() { "-nan" } else { "nan" })
+    }
+    else {
+        format!("{:.2}", f64::from(x))
+    }
+}
+
+/// The result of an arithmetic operation with its NaN signed the way x86
+/// signs it: a NaN operand passes through and a new NaN is negative. The
+/// optimizer would otherwise fold some NaNs to a positive one.
+#[allow(dead_code)]
+fn teeny_nan(result: Num, a: Num, b: Num) -> Num {
+    if !result.is_nan() {
+        result
+    }
+    else if a.is_nan() {
+        a
+    }
+    else if b.is_nan() {
+        b
+    }
+    else {
+        -Num::NAN
+    }
+}
+
+/// Reads numbers the way C's scanf("%f") or scanf("%lf") does.
+#[allow(dead_code)]
+struct TeenyInput<'a, R: BufRead> {
+    input: &'a mut R,
+}
+
+#[allow(dead_code)]
+impl<'a, R: BufRead> TeenyInput<'a, R> {
+    fn peek(&mut self) -> Option<u8> {
+        match self.input.fill_buf() {
+            Ok(buf) if !buf.is_empty() => Some(buf[0]),
+            _ => None,
+        }
+    }
+
+    fn advance(&mut self, text: &mut String) {
+        if let Some(c) = self.peek() {
+            text.push(c as char);
+            self.input.consume(1);
+        }
+    }
+
+    fn is_space(c: Option<u8>) -> bool {
+        matches!(c, Some(b' ' | b'\t'..=b'\r'))
+    }
+
+    fn digits(&mut self, text: &mut String, hex: bool) -> usize {
+        let mut n = 0;
+        while self.peek().is_some_and(|c| if hex { c.is_ascii_hexdigit() } else { c.is_ascii_digit() }) {
+            self.advance(text);
+            n += 1;
+        }
+        n
+    }
+
+    /// Whether the next letters are `letters`, ignoring case. Like glibc,
+    /// this consumes the first letter that doesn't match.
+    fn word(&mut self, letters: &str) -> bool {
+        let mut text = String::new();
+        for letter in letters.bytes() {
+            let c = self.peek();
+            self.advance(&mut text);
+            if c.map(|c| c.to_ascii_lowercase()) != Some(letter) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// The digits of an exponent introduced by `letter`, with their sign.
+    /// The letter is consumed even if no digits follow, which leaves the
+    /// exponent out.
+    fn exponent(&mut self, letter: u8) -> String {
+        let mut text = String::new();
+        if self.peek().map(|c| c.to_ascii_lowercase()) != Some(letter) {
+            return text;
+        }
+        self.input.consume(1);
+        if let Some(b'+' | b'-') = self.peek() {
+            self.advance(&mut text);
+        }
+        if self.digits(&mut text, false) == 0 {
+            text.clear();
+        }
+        text
+    }
+
+    /// Skip what scanf("%*s") does after a failed read.
+    fn skip(&mut self) -> Num {
+        let mut skipped = String::new();
+        while Self::is_space(self.peek()) {
+            self.input.consume(1);
+        }
+        while self.peek().is_some() && !Self::is_space(self.peek()) {
+            self.advance(&mut skipped);
+        }
+        0.0
+    }
+
+    /// The new value of `old` after
+    ///     if (0 == scanf("%f", &old)) { old = 0; scanf("%*s"); }
+    fn read_num(&mut self, old: Num) -> Num {
+        let mut text = String::new();
+        while Self::is_space(self.peek()) {
+            self.input.consume(1);
+        }
+        if self.peek().is_none() {
+            return old;
+        }
+        if let Some(b'+' | b'-') = self.peek() {
+            self.advance(&mut text);
+        }
+        let sign = if text == "-" { -1.0 } else { 1.0 };
+        match self.peek().map(|c| c.to_ascii_lowercase()) {
+            Some(b'i') => {
+                if !self.word("inf") {
+                    return self.skip();
+                }
+                if self.peek().map(|c| c.to_ascii_lowercase()) == Some(b'i') && !self.word("inity") {
+                    return self.skip();
+                }
+                return sign * Num::INFINITY;
+            }
+            Some(b'n') => {
+                return if self.word("nan") { Num::NAN.copysign(sign) } else { self.skip() };
+            }
+            _ => {}
+        }
+        let mut digits = String::new();
+        let mut n = self.digits(&mut digits, false);
+        if digits == "0" && matches!(self.peek(), Some(b'x' | b'X')) {
+            self.input.consume(1);
+            let mut digits = String::new();
+            self.digits(&mut digits, true);
+            let mut fraction = String::new();
+            if self.peek() == Some(b'.') {
+                self.input.consume(1);
+                self.digits(&mut fraction, true);
+            }
+            else if digits.is_empty() {
+                return self.skip();
+            }
+            if digits.is_empty() && fraction.is_empty() {
+                return sign * 0.0;
+            }
+            let exponent = self.exponent(b'p');
+            let exponent = match exponent.parse::<i64>() {
+                Ok(e) => e.clamp(-100000, 100000),
+                Err(_) if exponent.starts_with('-') => -100000,
+                Err(_) if !exponent.is_empty() => 100000,
+                Err(_) => 0,
+            };
+            return sign * teeny_hex(&(digits + &fraction), exponent - 4 * fraction.len() as i64);
+        }
+        text.push_str(&digits);
+        if self.peek() == Some(b'.') {
+            self.advance(&mut text);
+            n += self.digits(&mut text, false);
+        }
+        if n == 0 {
+            return self.skip();
+        }
+        let exponent = self.exponent(b'e');
+        if !exponent.is_empty() {
+            text.push('e');
+            text.push_str(&exponent);
+        }
+        text.parse().unwrap_or(0.0)
+    }
+}
+
+/// The integer with these hexadecimal digits times 2^exp, rounded to the
+/// nearest Num, ties to even, as strtof and strtod do
+#[allow(dead_code)]
+fn teeny_hex(digits: &str, mut exp: i64) -> Num {
+    // The first 61 bits or so, and whether any after them are set.
+    let mut man = 0u64;
+    let mut sticky = false;
+    for c in digits.chars() {
+        let d = u64::from(c.to_digit(16).unwrap());
+        if man >> 60 == 0 {
+            man = man * 16 + d;
+        }
+        else {
+            exp += 4;
+            sticky |= d != 0;
+        }
+    }
+    if man == 0 {
+        return 0.0;
+    }
+    let bits = Num::MANTISSA_DIGITS as i64;
+    let lsb = (exp + 64 - i64::from(man.leading_zeros()) - bits).max(i64::from(Num::MIN_EXP) - bits);
+    if lsb > exp {
+        let shift = (lsb - exp).min(65) as u32;
+        let wide = u128::from(man);
+        let rem = wide & ((1 << shift) - 1);
+        let half = 1 << (shift - 1);
+        man = (wide >> shift) as u64;
+        if rem > half || (rem == half && (sticky || man & 1 == 1)) {
+            man += 1;
+        }
+        exp = lsb;
+    }
+    // In two steps, since 2^exp on its own may not fit in an f64.
+    let pow2 = |e: i64| if e > 1023 { f64::INFINITY } else { f64::from_bits(((e + 1023) as u64) << 52) };
+    (man as f64 * pow2(exp / 2) * pow2(exp - exp / 2)) as Num
+}
+"#;
+
+pub struct RustEmitter {
+    passes: Passes,
+    header: String,
+    code: String,
+    number_type: NumberType,
+}
+
+impl RustEmitter {
+    pub fn new(passes: Passes) -> Self {
+        Self {
+            passes,
+            header: String::new(),
+            code: String::new(),
+            number_type: NumberType::F32,
+        }
+    }
+
+    /// The Rust type of every value
+    fn ty(&self) -> &'static str {
+        match self.number_type {
+            NumberType::F32 => "f32",
+            NumberType::F64 => "f64",
+        }
+    }
+
+    /// Render an operand as a Rust expression of type Num
+    fn operand(&self, o: &Operand) -> String {
+        match o {
+            Operand::Const(c) if c.is_nan() => String::from("Num::NAN"),
+            Operand::Const(c) if c.is_infinite() => {
+                String::from(if *c < 0.0 { "(-Num::INFINITY)" } else { "Num::INFINITY" })
+            }
+            Operand::Const(c) if c.is_sign_negative() => format!("({}_{})", self.number_type.text(*c), self.ty()),
+            Operand::Const(c) => format!("{}_{}", self.number_type.text(*c), self.ty()),
+            Operand::Var(v) => format!("v_{}", v),
+            Operand::Temp(t) => format!("t{}", t),
+        }
+    }
+
+    fn line(&mut self, indent: usize, code: &str) {
+        self.emit(&"    ".repeat(indent));
+        self.emit_line(code);
+    }
+
+    fn instruction(&mut self, instr: &Instr) {
+        match instr {
+            Instr::PrintStr { text, newline } => {
+                let mut text = text.clone();
+                if *newline {
+                    text.push('\n');
+                }
+                self.line(4, &format!("out.write_all({:?}.as_bytes())?;", text));
+            }
+            Instr::Print { value, newline } => {
+                self.line(4, &format!("out.write_all(teeny_format({}).as_bytes())?;", self.operand(value)));
+                if *newline {
+                    self.line(4, "out.write_all(b\"\\n\")?;");
+                }
+            }
+            Instr::Copy { dest, src } => {
+                self.line(4, &format!("{} = {};", self.operand(dest), self.operand(src)));
+            }
+            Instr::Neg { dest, src } => {
+                self.line(4, &format!("{} = -{};", self.operand(dest), self.operand(src)));
+            }
+            Instr::Binary { dest, op, left, right } if op.is_comparison() => {
+                let c = format!("{} {} {}", self.operand(left), op.symbol(), self.operand(right));
+                self.line(4, &format!("{} = Num::from(u8::from({}));", self.operand(dest), c));
+            }
+            Instr::Binary { dest, op, left, right } => {
+                let (l, r) = (self.operand(left), self.operand(right));
+                let e = format!("teeny_nan({} {} {}, {}, {})", l, op.symbol(), r, l, r);
+                self.line(4, &format!("{} = {};", self.operand(dest), e));
+            }
+            Instr::Input(name) => {
+                self.line(4, &format!("v_{} = input.read_num(v_{});", name, name));
+            }
+        }
+    }
+}
+
+impl Emitter for RustEmitter {
+    fn emit(&mut self, code: &str) {
+        self.code.push_str(code);
+    }
+
+    fn emit_line(&mut self, code: &str) {
+        self.emit(code);
+        self.code.push('\n');
+    }
+
+    fn header_line(&mut self, code: &str) {
+        self.header.push_str(code);
+        self.header.push('\n');
+    }
+
+    fn write(&self, out: &mut dyn Write) -> io::Result<()> {
+        out.write_all(self.header.as_bytes())?;
+        out.write_all(self.code.as_bytes())
+    }
+
+    fn program(&mut self, program: &Program) {
+        let ir = opt::lower(program, &self.passes);
+        self.number_type = ir.number_type;
+        self.header_line("// Generated by ttrust.");
+        self.header_line("use std::io::{BufRead, Write};");
+        self.header_line("");
+        self.header_line("/// The type of every Teeny value");
+        self.header_line(&format!("type Num = {};", self.ty()));
+        self.header_line(RUNTIME);
+
+        self.emit_line("/// Run the program, reading INPUT from `input` and writing PRINT to `out`.");
+        self.emit_line("#[allow(unused_assignments, unused_mut, unused_variables, unreachable_code, clippy::all)]");
+        self.emit_line("pub fn run(input: &mut impl BufRead, out: &mut impl Write) -> std::io::Result<()> {");
+        self.line(1, "let mut input = TeenyInput { input };");
+        for v in ir.variables.iter() {
+            self.line(1, &format!("let mut v_{}: Num = 0.0;", v));
+        }
+        for t in 1..=ir.num_temps {
+            self.line(1, &format!("let mut t{}: Num = 0.0;", t));
+        }
+        self.line(1, "let mut state = 0;");
+        self.line(1, "loop {");
+        self.line(2, "match state {");
+        for (i, block) in ir.blocks.iter().enumerate() {
+            self.line(3, &format!("{} => {{", i));
+            for instr in block.instrs.iter() {
+                self.instruction(instr);
+            }
+            match &block.terminator {
+                Terminator::Jump(t) => self.line(4, &format!("state = {};", t)),
+                Terminator::Branch { condition, then, otherwise } => {
+                    let c = self.operand(condition);
+                    self.line(4, &format!("state = if {} != 0.0 {{ {} }} else {{ {} }};", c, then, otherwise));
+                }
+                Terminator::Return => self.line(4, "return out.flush();"),
+            }
+            self.line(3, "}");
+        }
+        self.line(3, "_ => unreachable!(),");
+        self.line(2, "}");
+        self.line(1, "}");
+        self.emit_line("}");
+        self.emit_line("");
+        self.emit_line("#[allow(dead_code)]");
+        self.emit_line("fn main() {");
+        self.line(1, "let stdin = std::io::stdin();");
+        self.line(1, "let stdout = std::io::stdout();");
+        self.line(1, "if let Err(e) = run(&mut stdin.lock(), &mut std::io::BufWriter::new(stdout.lock())) {");
+        self.line(2, "eprintln!(\"{}\", e);");
+        self.line(2, "std::process::exit(1);");
+        self.line(1, "}");
+        self.emit_line("}");
+    }
+}
@@ -0,0 +1,267 @@
+// ssa.rs
+// Static single assignment form for the IR. Construction follows Cytron et
+// al.: dominators, dominance frontiers, phi insertion, then renaming every
+// variable definition to a fresh temporary.
+
+use crate::ir::{Instr, Ir, Operand, Phi, Terminator};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// The immediate dominator of every block. The entry block is its own
+/// dominator. Uses the iterative algorithm of Cooper, Harvey and Kennedy,
+/// and expects every block to be reachable.
+pub fn dominators(ir: &Ir) -> Vec<usize> {
+    let preds = ir.predecessors();
+
+    // Reverse postorder from the entry.
+    let mut order = Vec::new();
+    let mut visited = vec![false; ir.blocks.len()];
+    postorder(ir, 0, &mut visited, &mut order);
+    order.reverse();
+    let mut rpo_index = vec![0; ir.blocks.len()];
+    for (i, &b) in order.iter().enumerate() {
+        rpo_index[b] = i;
+    }
+
+    let mut idom: Vec<Option<usize>> = vec![None; ir.blocks.len()];
+    idom[0] = Some(0);
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &b in order.iter().skip(1) {
+            let mut new_idom = None;
+            for &p in preds[b].iter() {
+                if idom[p].is_none() {
+                    continue;
+                }
+                new_idom = Some(match new_idom {
+                    None => p,
+                    Some(other) => intersect(&idom, &rpo_index, p, other),
+                });
+            }
+            if new_idom != idom[b] {
+                idom[b] = new_idom;
+                changed = true;
+            }
+        }
+    }
+    idom.into_iter().map(|d| d.unwrap_or(0)).collect()
+}
+
+fn postorder(ir: &Ir, b: usize, visited: &mut Vec<bool>, order: &mut Vec<usize>) {
+    visited[b] = true;
+    for s in ir.blocks[b].terminator.successors() {
+        if !visited[s] {
+            postorder(ir, s, visited, order);
+        }
+    }
+    order.push(b);
+}
+
+fn intersect(idom: &[Option<usize>], rpo_index: &[usize], mut a: usize, mut b: usize) -> usize {
+    while a != b {
+        while rpo_index[a] > rpo_index[b] {
+            a = idom[a].unwrap();
+        }
+        while rpo_index[b] > rpo_index[a] {
+            b = idom[b].unwrap();
+        }
+    }
+    a
+}
+
+/// The children of every block in the dominator tree
+pub fn dominator_tree(idom: &[usize]) -> Vec<Vec<usize>> {
+    let mut children = vec![Vec::new(); idom.len()];
+    for (b, &d) in idom.iter().enumerate().skip(1) {
+        children[d].push(b);
+    }
+    children
+}
+
+fn dominance_frontiers(ir: &Ir, idom: &[usize]) -> Vec<BTreeSet<usize>> {
+    let mut df = vec![BTreeSet::new(); ir.blocks.len()];
+    for (b, preds) in ir.predecessors().iter().enumerate() {
+        if preds.len() < 2 {
+            continue;
+        }
+        for &p in preds.iter() {
+            let mut runner = p;
+            while runner != idom[b] {
+                df[runner].insert(b);
+                runner = idom[runner];
+            }
+        }
+    }
+    df
+}
+
+/// The variable an instruction assigns, if any
+fn defined_var(instr: &Instr) -> Option<&str> {
+    match instr {
+        Instr::Copy { dest: Operand::Var(v), .. }
+        | Instr::Neg { dest: Operand::Var(v), .. }
+        | Instr::Binary { dest: Operand::Var(v), .. } => Some(v),
+        Instr::Input(v) => Some(v),
+        _ => None,
+    }
+}
+
+/// Rewrite the IR into SSA form. Afterwards every variable read and write
+/// goes through temporaries, except around INPUT, which still reads into
+/// the variable itself.
+pub fn construct(ir: &mut Ir) {
+    ir.remove_unreachable();
+    let idom = dominators(ir);
+    let df = dominance_frontiers(ir, &idom);
+
+    // Every variable is defined on entry, where it starts at zero.
+    let mut phi_vars: Vec<Vec<String>> = vec![Vec::new(); ir.blocks.len()];
+    for v in ir.variables.clone() {
+        let mut work: Vec<usize> = vec![0];
+        for (b, block) in ir.blocks.iter().enumerate() {
+            if block.instrs.iter().any(|i| defined_var(i) == Some(&v)) {
+                work.push(b);
+            }
+        }
+        let mut has_phi = BTreeSet::new();
+        while let Some(b) = work.pop() {
+            for &f in df[b].iter() {
+                if has_phi.insert(f) {
+                    let dest = ir.temp();
+                    ir.blocks[f].phis.push(Phi { dest, args: Vec::new() });
+                    phi_vars[f].push(v.clone());
+                    work.push(f);
+                }
+            }
+        }
+    }
+
+    let mut stacks: BTreeMap<String, Vec<Operand>> = BTreeMap::new();
+    for v in ir.variables.iter() {
+        stacks.insert(v.clone(), vec![Operand::Const(0.0)]);
+    }
+    let children = dominator_tree(&idom);
+    rename(ir, 0, &children, &phi_vars, &mut stacks);
+}
+
+fn current(stacks: &BTreeMap<String, Vec<Operand>>, o: &Operand) -> Operand {
+    match o {
+        Operand::Var(v) => stacks[v].last().unwrap().clone(),
+        other => other.clone(),
+    }
+}
+
+/// Give an assignment to a variable a fresh temporary instead
+fn define(ir: &mut Ir, stacks: &mut BTreeMap<String, Vec<Operand>>, pushed: &mut Vec<String>, dest: Operand) -> Operand {
+    match dest {
+        Operand::Var(v) => {
+            let t = ir.temp();
+            stacks.get_mut(&v).unwrap().push(Operand::Temp(t));
+            pushed.push(v);
+            Operand::Temp(t)
+        }
+        other => other,
+    }
+}
+
+fn rename(ir: &mut Ir, b: usize, children: &[Vec<usize>], phi_vars: &[Vec<String>], stacks: &mut BTreeMap<String, Vec<Operand>>) {
+    let mut pushed = Vec::new();
+    for (i, v) in phi_vars[b].iter().enumerate() {
+        let dest = ir.blocks[b].phis[i].dest;
+        stacks.get_mut(v).unwrap().push(Operand::Temp(dest));
+        pushed.push(v.clone());
+    }
+
+    let instrs = std::mem::take(&mut ir.blocks[b].instrs);
+    let mut out = Vec::with_capacity(instrs.len());
+    for instr in instrs {
+        match instr {
+            Instr::Copy { dest, src } => {
+                let src = current(stacks, &src);
+                out.push(Instr::Copy { dest: define(ir, stacks, &mut pushed, dest), src });
+            }
+            Instr::Neg { dest, src } => {
+                let src = current(stacks, &src);
+                out.push(Instr::Neg { dest: define(ir, stacks, &mut pushed, dest), src });
+            }
+            Instr::Binary { dest, op, left, right } => {
+                let left = current(stacks, &left);
+                let right = current(stacks, &right);
+                out.push(Instr::Binary { dest: define(ir, stacks, &mut pushed, dest), op, left, right });
+            }
+            Instr::Print { value, newline } => {
+                out.push(Instr::Print { value: current(stacks, &value), newline });
+            }
+            Instr::Input(v) => {
+                // scanf keeps the old value at end of input, so it has to
+                // be in the variable first.
+                let old = current(stacks, &Operand::Var(v.clone()));
+                out.push(Instr::Copy { dest: Operand::Var(v.clone()), src: old });
+                out.push(Instr::Input(v.clone()));
+                let dest = define(ir, stacks, &mut pushed, Operand::Var(v.clone()));
+                out.push(Instr::Copy { dest, src: Operand::Var(v) });
+            }
+            other => out.push(other),
+        }
+    }
+    ir.blocks[b].instrs = out;
+    if let Terminator::Branch { condition, .. } = &mut ir.blocks[b].terminator {
+        *condition = current(stacks, condition);
+    }
+
+    let mut successors = ir.blocks[b].terminator.successors();
+    // A branch with both arms the same only gets one argument.
+    successors.dedup();
+    for s in successors {
+        for (i, v) in phi_vars[s].iter().enumerate() {
+            let value = stacks[v].last().unwrap().clone();
+            ir.blocks[s].phis[i].args.push((b, value));
+        }
+    }
+
+    for &c in children[b].iter() {
+        rename(ir, c, children, phi_vars, stacks);
+    }
+    for v in pushed {
+        stacks.get_mut(&v).unwrap().pop();
+    }
+}
+
+/// Replace every phi with copies through a new variable: each predecessor
+/// stores its value there and the phi's block loads it. Temporaries keep
+/// their single assignment, and a value that is read after the phi's
+/// block has been re-entered is never overwritten.
+pub fn destruct(ir: &mut Ir) {
+    for b in 0..ir.blocks.len() {
+        let phis = std::mem::take(&mut ir.blocks[b].phis);
+        let mut loads = Vec::new();
+        for phi in phis {
+            let slot = format!("{}phi", phi.dest);
+            for (p, value) in phi.args {
+                ir.blocks[p].instrs.push(Instr::Copy { dest: Operand::Var(slot.clone()), src: value });
+            }
+            loads.push(Instr::Copy { dest: Operand::Temp(phi.dest), src: Operand::Var(slot.clone()) });
+            ir.variables.push(slot);
+        }
+        ir.blocks[b].instrs.splice(0..0, loads);
+    }
+
+    // Variables that are now only touched by INPUT, or not at all, are
+    // still declared by the backends, so drop the unused ones.
+    let mut used = BTreeSet::new();
+    for block in ir.blocks.iter() {
+        for instr in block.instrs.iter() {
+            match instr {
+                Instr::Input(v) => {
+                    used.insert(v.clone());
+                }
+                _ => {
+                    if let Some(v) = defined_var(instr) {
+                        used.insert(v.to_string());
+                    }
+                }
+            }
+        }
+    }
+    ir.variables.retain(|v| used.contains(v));
+}
@@ -1,3 +1,5 @@
+use std::fmt;
+
 #[derive(Copy, Clone, Debug)]
 #[repr(i32)]
 pub enum TokenType {
@@ -7,6 +9,12 @@ pub enum TokenType {
     Number,
     Ident,
     String,
+    /// A `##` comment, with the text after the marker
+    DocComment,
+    /// Spaces and tabs, only produced when keeping trivia
+    Whitespace,
+    /// A `#` or `#[ ]#` comment, only produced when keeping trivia
+    Comment,
     // Keywords
     Label = 101,
     Goto,
@@ -45,19 +53,62 @@ impl PartialEq for TokenType {
     }
 }
 
+/// How keywords may be spelled
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Dialect {
+    /// Keywords are upper case, and `print` is an identifier
+    #[default]
+    Strict,
+    /// `print`, `Print` and `PRINT` are all the keyword
+    CaseInsensitive,
+}
+
+impl Dialect {
+    /// Look a dialect up by the name used on the command line
+    pub fn by_name(name: &str) -> Option<Dialect> {
+        match name {
+            "strict" => Some(Dialect::Strict),
+            "case-insensitive" => Some(Dialect::CaseInsensitive),
+            _ => None,
+        }
+    }
+}
+
+/// Where a token is in the source
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Span {
+    /// Byte offsets of the first character and just past the last
+    pub start: usize,
+    pub end: usize,
+    /// Line and column of the first character, counting from 1 in
+    /// characters
+    pub line: usize,
+    pub column: usize,
+}
+
 #[derive(Clone)]
 pub struct Token {
     pub text: String,
     pub kind: TokenType,
+    pub span: Span,
 }
 
 impl Token {
     /// Create a new token with a given text and type. We can't
     /// use the keyword type, so we use kind here.
-    pub fn new(token_text: &String, kind: TokenType) -> Self {
+    pub fn new(token_text: &str, kind: TokenType) -> Self {
         Self {
-            text: token_text.clone(),
-            kind
+            text: token_text.to_string(),
+            kind,
+            span: Span::default(),
+        }
+    }
+
+    /// Return the keyword a word spells in a dialect, or Unknown
+    pub fn keyword(token_text: &str, dialect: Dialect) -> TokenType {
+        match dialect {
+            Dialect::Strict => Token::check_if_keyword(token_text),
+            Dialect::CaseInsensitive => Token::check_if_keyword(&token_text.to_ascii_uppercase()),
         }
     }
 
@@ -89,6 +140,18 @@ impl Default for Token {
         Self {
             text: String::new(),
             kind: TokenType::Unknown,
+            span: Span::default(),
         }
     }
 }
+
+/// One line of `--emit=tokens`: position, byte range, type and text
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}\t{}..{}\t{:?}\t{:?}",
+            self.span.line, self.span.column, self.span.start, self.span.end, self.kind, self.text
+        )
+    }
+}
@@ -0,0 +1,787 @@
+// watemitter.rs
+// The WebAssembly text emitter. Produces a WASI command module (.wat)
+// whose output matches the C backend byte for byte.
+
+use crate::ast::{BinOp, NumberType, Program};
+use crate::emit::Emitter;
+use crate::ir::{Instr, Ir, Operand, Terminator};
+use crate::opt::{self, Passes};
+use std::io::{self, Write};
+
+/// Where string literals start in linear memory. Everything below is
+/// scratch space used by the runtime (see RUNTIME).
+const DATA_START: usize = 8192;
+
+/// Runtime support: output, the %.2f formatter and a scanf("%lf") work-alike.
+/// Both work on f64; f32 values are converted on the way in and out.
+///
+/// Memory map:
+///   0..8       iovec handed to fd_write/fd_read
+///   8..12      bytes written/read
+///   16..160    36 32-bit limbs used to print the integer part of a number
+///   160..167   "-naninf"
+///   168..512   number formatting buffer, filled backwards from 512
+///   512..4608  stdin buffer
+///   4608..6528 three 160-limb integers used to round input exactly
+const RUNTIME: &str = r#"
+  (global $in_pos (mut i32) (i32.const 0))
+  (global $in_len (mut i32) (i32.const 0))
+
+  (func $write (param $ptr i32) (param $len i32)
+    (i32.store (i32.const 0) (local.get $ptr))
+    (i32.store (i32.const 4) (local.get $len))
+    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8))))
+
+  ;; Print an f64 exactly like printf("%.2f", x) does with glibc. The value
+  ;; is man * 2^e, so the integer part and the rounded two fraction digits
+  ;; can be worked out exactly with integers.
+  (func $print_num (param $x f64)
+    (local $bits i64) (local $exp i32) (local $man i64) (local $p i32)
+    (local $shift i32) (local $i i32) (local $nz i32)
+    (local $int i64) (local $mask i64) (local $scaled i64) (local $q i64)
+    (local $rem i64) (local $half i64) (local $cur i64) (local $carry i64)
+    (local $lo i64) (local $hi i64)
+    (local.set $bits (i64.reinterpret_f64 (local.get $x)))
+    (local.set $exp (i32.wrap_i64 (i64.and (i64.shr_u (local.get $bits) (i64.const 52)) (i64.const 2047))))
+    (local.set $man (i64.and (local.get $bits) (i64.const 0xfffffffffffff)))
+    (if (i32.eq (local.get $exp) (i32.const 2047))
+      (then
+        (if (i64.lt_s (local.get $bits) (i64.const 0))
+          (then (call $write (i32.const 160) (i32.const 1))))
+        (if (i64.ne (local.get $man) (i64.const 0))
+          (then (call $write (i32.const 161) (i32.const 3)))
+          (else (call $write (i32.const 164) (i32.const 3))))
+        (return)))
+    (if (local.get $exp)
+      (then (local.set $man (i64.or (local.get $man) (i64.const 0x10000000000000))))
+      (else (local.set $exp (i32.const 1))))
+    (local.set $exp (i32.sub (local.get $exp) (i32.const 1075)))
+    (local.set $i (i32.const 16))
+    (loop $clear
+      (i64.store (local.get $i) (i64.const 0))
+      (local.set $i (i32.add (local.get $i) (i32.const 8)))
+      (br_if $clear (i32.lt_u (local.get $i) (i32.const 160))))
+    (local.set $q (i64.const 0))
+    (if (i32.ge_s (local.get $exp) (i32.const 0))
+      (then
+        ;; A whole number, possibly much wider than 64 bits. man shifted by
+        ;; exp % 32 takes up to 85 bits: three limbs from limb exp / 32.
+        (local.set $shift (i32.and (local.get $exp) (i32.const 31)))
+        (local.set $lo (i64.shl (i64.and (local.get $man) (i64.const 0xffffffff))
+                                (i64.extend_i32_u (local.get $shift))))
+        (local.set $hi (i64.shl (i64.shr_u (local.get $man) (i64.const 32))
+                                (i64.extend_i32_u (local.get $shift))))
+        (local.set $cur (i64.add (i64.shr_u (local.get $lo) (i64.const 32))
+                                 (i64.and (local.get $hi) (i64.const 0xffffffff))))
+        (local.set $i (i32.add (i32.const 16) (i32.shl (i32.shr_u (local.get $exp) (i32.const 5)) (i32.const 2))))
+        (i64.store32 (local.get $i) (local.get $lo))
+        (i64.store32 (i32.add (local.get $i) (i32.const 4)) (local.get $cur))
+        (i64.store32 (i32.add (local.get $i) (i32.const 8))
+                     (i64.add (i64.shr_u (local.get $cur) (i64.const 32))
+                              (i64.shr_u (local.get $hi) (i64.const 32)))))
+      (else
+        (local.set $shift (i32.sub (i32.const 0) (local.get $exp)))
+        ;; Anything below 2^-9 prints as 0.00.
+        (if (i32.le_s (local.get $shift) (i32.const 61))
+          (then
+            (local.set $mask (i64.sub (i64.shl (i64.const 1) (i64.extend_i32_u (local.get $shift))) (i64.const 1)))
+            (local.set $int (i64.shr_u (local.get $man) (i64.extend_i32_u (local.get $shift))))
+            (local.set $scaled (i64.mul (i64.and (local.get $man) (local.get $mask)) (i64.const 100)))
+            (local.set $q (i64.shr_u (local.get $scaled) (i64.extend_i32_u (local.get $shift))))
+            (local.set $rem (i64.and (local.get $scaled) (local.get $mask)))
+            (local.set $half (i64.shl (i64.const 1) (i64.extend_i32_u (i32.sub (local.get $shift) (i32.const 1)))))
+            ;; Round half to even, as glibc does.
+            (if (i32.or (i64.gt_u (local.get $rem) (local.get $half))
+                        (i32.and (i64.eq (local.get $rem) (local.get $half))
+                                 (i32.wrap_i64 (i64.and (local.get $q) (i64.const 1)))))
+              (then (local.set $q (i64.add (local.get $q) (i64.const 1)))))
+            (if (i64.eq (local.get $q) (i64.const 100))
+              (then
+                (local.set $q (i64.const 0))
+                (local.set $int (i64.add (local.get $int) (i64.const 1)))))
+            (i64.store (i32.const 16) (local.get $int))))))
+    (local.set $p (i32.const 512))
+    (local.set $p (i32.sub (local.get $p) (i32.const 1)))
+    (i32.store8 (local.get $p) (i32.wrap_i64 (i64.add (i64.rem_u (local.get $q) (i64.const 10)) (i64.const 48))))
+    (local.set $p (i32.sub (local.get $p) (i32.const 1)))
+    (i32.store8 (local.get $p) (i32.wrap_i64 (i64.add (i64.div_u (local.get $q) (i64.const 10)) (i64.const 48))))
+    (local.set $p (i32.sub (local.get $p) (i32.const 1)))
+    (i32.store8 (local.get $p) (i32.const 46))
+    ;; Peel decimal digits off the limbs until nothing is left.
+    (loop $digits
+      (local.set $carry (i64.const 0))
+      (local.set $nz (i32.const 0))
+      (local.set $i (i32.const 160))
+      (loop $div
+        (local.set $i (i32.sub (local.get $i) (i32.const 4)))
+        (local.set $cur (i64.or (i64.shl (local.get $carry) (i64.const 32))
+                                (i64.load32_u (local.get $i))))
+        (i64.store32 (local.get $i) (i64.div_u (local.get $cur) (i64.const 10)))
+        (local.set $nz (i32.or (local.get $nz) (i64.ge_u (local.get $cur) (i64.const 10))))
+        (local.set $carry (i64.rem_u (local.get $cur) (i64.const 10)))
+        (br_if $div (i32.gt_u (local.get $i) (i32.const 16))))
+      (local.set $p (i32.sub (local.get $p) (i32.const 1)))
+      (i32.store8 (local.get $p) (i32.wrap_i64 (i64.add (local.get $carry) (i64.const 48))))
+      (br_if $digits (local.get $nz)))
+    (if (i64.lt_s (local.get $bits) (i64.const 0))
+      (then
+        (local.set $p (i32.sub (local.get $p) (i32.const 1)))
+        (i32.store8 (local.get $p) (i32.const 45))))
+    (call $write (local.get $p) (i32.sub (i32.const 512) (local.get $p))))
+
+  ;; The next byte of stdin without consuming it, or -1 at end of file.
+  (func $peek (result i32)
+    (if (i32.ge_u (global.get $in_pos) (global.get $in_len))
+      (then
+        (i32.store (i32.const 0) (i32.const 512))
+        (i32.store (i32.const 4) (i32.const 4096))
+        (global.set $in_pos (i32.const 0))
+        (global.set $in_len (i32.const 0))
+        (if (i32.eqz (call $fd_read (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 8)))
+          (then (global.set $in_len (i32.load (i32.const 8)))))
+        (if (i32.eqz (global.get $in_len))
+          (then (return (i32.const -1))))))
+    (i32.load8_u (i32.add (i32.const 512) (global.get $in_pos))))
+
+  (func $advance
+    (global.set $in_pos (i32.add (global.get $in_pos) (i32.const 1))))
+
+  (func $is_space (param $c i32) (result i32)
+    (i32.or (i32.eq (local.get $c) (i32.const 32))
+            (i32.lt_u (i32.sub (local.get $c) (i32.const 9)) (i32.const 5))))
+
+  (func $is_digit (param $c i32) (result i32)
+    (i32.lt_u (i32.sub (local.get $c) (i32.const 48)) (i32.const 10)))
+
+  ;; Integers of 160 32-bit limbs, least significant first.
+  (func $big_set (param $a i32) (param $v i64)
+    (local $i i32)
+    (loop $clear
+      (i32.store (i32.add (local.get $a) (local.get $i)) (i32.const 0))
+      (local.set $i (i32.add (local.get $i) (i32.const 4)))
+      (br_if $clear (i32.lt_u (local.get $i) (i32.const 640))))
+    (i64.store (local.get $a) (local.get $v)))
+
+  (func $big_copy (param $dst i32) (param $src i32)
+    (local $i i32)
+    (loop $copy
+      (i32.store (i32.add (local.get $dst) (local.get $i))
+                 (i32.load (i32.add (local.get $src) (local.get $i))))
+      (local.set $i (i32.add (local.get $i) (i32.const 4)))
+      (br_if $copy (i32.lt_u (local.get $i) (i32.const 640)))))
+
+  ;; a = a * f + add, for f and add below 2^32.
+  (func $big_mul (param $a i32) (param $f i64) (param $add i64)
+    (local $i i32) (local $cur i64)
+    (loop $limb
+      (local.set $cur (i64.add (i64.mul (i64.load32_u (i32.add (local.get $a) (local.get $i))) (local.get $f))
+                               (local.get $add)))
+      (i64.store32 (i32.add (local.get $a) (local.get $i)) (local.get $cur))
+      (local.set $add (i64.shr_u (local.get $cur) (i64.const 32)))
+      (local.set $i (i32.add (local.get $i) (i32.const 4)))
+      (br_if $limb (i32.lt_u (local.get $i) (i32.const 640)))))
+
+  ;; a = a * 10^n, or a * 2^n if $two is set.
+  (func $big_scale (param $a i32) (param $n i32) (param $two i32)
+    (block $done
+      (loop $more
+        (br_if $done (i32.le_s (local.get $n) (i32.const 0)))
+        (if (local.get $two)
+          (then
+            (if (i32.ge_s (local.get $n) (i32.const 31))
+              (then
+                (call $big_mul (local.get $a) (i64.const 0x80000000) (i64.const 0))
+                (local.set $n (i32.sub (local.get $n) (i32.const 31))))
+              (else
+                (call $big_mul (local.get $a) (i64.const 2) (i64.const 0))
+                (local.set $n (i32.sub (local.get $n) (i32.const 1))))))
+          (else
+            (if (i32.ge_s (local.get $n) (i32.const 9))
+              (then
+                (call $big_mul (local.get $a) (i64.const 1000000000) (i64.const 0))
+                (local.set $n (i32.sub (local.get $n) (i32.const 9))))
+              (else
+                (call $big_mul (local.get $a) (i64.const 10) (i64.const 0))
+                (local.set $n (i32.sub (local.get $n) (i32.const 1)))))))
+        (br $more))))
+
+  ;; -1, 0 or 1 as a is less than, equal to or greater than b.
+  (func $big_cmp (param $a i32) (param $b i32) (result i32)
+    (local $i i32) (local $x i32) (local $y i32)
+    (local.set $i (i32.const 640))
+    (loop $limb
+      (local.set $i (i32.sub (local.get $i) (i32.const 4)))
+      (local.set $x (i32.load (i32.add (local.get $a) (local.get $i))))
+      (local.set $y (i32.load (i32.add (local.get $b) (local.get $i))))
+      (if (i32.ne (local.get $x) (local.get $y))
+        (then (return (select (i32.const 1) (i32.const -1) (i32.gt_u (local.get $x) (local.get $y))))))
+      (br_if $limb (local.get $i)))
+    (i32.const 0))
+
+  ;; Consume decimal digits, returning how many there were. The first
+  ;; 18 or so go into $mant for a first guess at the value, and the first
+  ;; 800 significant ones into the integer at 4608 ($ndig of them, scaled
+  ;; by 10^$dexp). A nonzero digit past those sets $sticky.
+  (global $mant (mut i64) (i64.const 0))
+  (global $exp10 (mut i32) (i32.const 0))
+  (global $ndig (mut i32) (i32.const 0))
+  (global $dexp (mut i32) (i32.const 0))
+  (global $sticky (mut i32) (i32.const 0))
+  (func $digits (param $fraction i32) (result i32)
+    (local $c i32) (local $n i32) (local $d i64)
+    (block $done
+      (loop $more
+        (local.set $c (call $peek))
+        (br_if $done (i32.eqz (call $is_digit (local.get $c))))
+        (local.set $d (i64.extend_i32_u (i32.sub (local.get $c) (i32.const 48))))
+        (if (i64.lt_u (global.get $mant) (i64.const 100000000000000000))
+          (then
+            (global.set $mant (i64.add (i64.mul (global.get $mant) (i64.const 10)) (local.get $d)))
+            (if (local.get $fraction)
+              (then (global.set $exp10 (i32.sub (global.get $exp10) (i32.const 1))))))
+          (else
+            (if (i32.eqz (local.get $fraction))
+              (then (global.set $exp10 (i32.add (global.get $exp10) (i32.const 1)))))))
+        (if (i32.lt_u (global.get $ndig) (i32.const 800))
+          (then
+            (if (i32.or (global.get $ndig) (i64.ne (local.get $d) (i64.const 0)))
+              (then
+                (call $big_mul (i32.const 4608) (i64.const 10) (local.get $d))
+                (global.set $ndig (i32.add (global.get $ndig) (i32.const 1)))))
+            (if (local.get $fraction)
+              (then (global.set $dexp (i32.sub (global.get $dexp) (i32.const 1))))))
+          (else
+            (if (i64.ne (local.get $d) (i64.const 0))
+              (then (global.set $sticky (i32.const 1))))
+            (if (i32.eqz (local.get $fraction))
+              (then (global.set $dexp (i32.add (global.get $dexp) (i32.const 1)))))))
+        (local.set $n (i32.add (local.get $n) (i32.const 1)))
+        (call $advance)
+        (br $more)))
+    (local.get $n))
+
+  ;; Compare the digits read with the point halfway between the positive
+  ;; f64 with these bits and the next one up, (2 * man + 1) * 2^(e - 1).
+  (func $cmp_mid (param $bits i64) (result i32)
+    (local $exp i32) (local $man i64)
+    (local.set $exp (i32.wrap_i64 (i64.shr_u (local.get $bits) (i64.const 52))))
+    (local.set $man (i64.and (local.get $bits) (i64.const 0xfffffffffffff)))
+    (if (local.get $exp)
+      (then (local.set $man (i64.or (local.get $man) (i64.const 0x10000000000000))))
+      (else (local.set $exp (i32.const 1))))
+    (local.set $exp (i32.sub (local.get $exp) (i32.const 1076)))
+    (call $big_copy (i32.const 5248) (i32.const 4608))
+    (call $big_set (i32.const 5888) (i64.add (i64.shl (local.get $man) (i64.const 1)) (i64.const 1)))
+    (if (i32.ge_s (global.get $dexp) (i32.const 0))
+      (then (call $big_scale (i32.const 5248) (global.get $dexp) (i32.const 0)))
+      (else (call $big_scale (i32.const 5888) (i32.sub (i32.const 0) (global.get $dexp)) (i32.const 0))))
+    (if (i32.ge_s (local.get $exp) (i32.const 0))
+      (then (call $big_scale (i32.const 5888) (local.get $exp) (i32.const 1)))
+      (else (call $big_scale (i32.const 5248) (i32.sub (i32.const 0) (local.get $exp)) (i32.const 1))))
+    (call $big_cmp (i32.const 5248) (i32.const 5888)))
+
+  ;; The f64 nearest the digits read, ties to even, as strtod gives. The
+  ;; first guess from $mant is off by at most a few units in the last
+  ;; place, and is moved one at a time until it is the nearest.
+  (func $nearest (result f64)
+    (local $value f64) (local $scale f64) (local $e i32) (local $bits i64) (local $c i32)
+    (if (i32.eqz (global.get $ndig))
+      (then (return (f64.const 0))))
+    (if (i32.gt_s (i32.add (global.get $ndig) (global.get $dexp)) (i32.const 310))
+      (then (return (f64.const inf))))
+    (if (i32.lt_s (i32.add (global.get $ndig) (global.get $dexp)) (i32.const -324))
+      (then (return (f64.const 0))))
+    ;; Powers of ten up to 1e22 are exact.
+    (local.set $value (f64.convert_i64_u (global.get $mant)))
+    (local.set $e (global.get $exp10))
+    (block $big_steps
+      (loop $step
+        (br_if $big_steps (i32.lt_u (i32.add (local.get $e) (i32.const 22)) (i32.const 45)))
+        (if (i32.gt_s (local.get $e) (i32.const 0))
+          (then
+            (local.set $value (f64.mul (local.get $value) (f64.const 1e22)))
+            (local.set $e (i32.sub (local.get $e) (i32.const 22))))
+          (else
+            (local.set $value (f64.div (local.get $value) (f64.const 1e22)))
+            (local.set $e (i32.add (local.get $e) (i32.const 22)))))
+        (br $step)))
+    (local.set $scale (f64.const 1))
+    (local.set $c (select (local.get $e) (i32.sub (i32.const 0) (local.get $e)) (i32.gt_s (local.get $e) (i32.const 0))))
+    (block $scaled
+      (loop $scale_more
+        (br_if $scaled (i32.eqz (local.get $c)))
+        (local.set $scale (f64.mul (local.get $scale) (f64.const 10)))
+        (local.set $c (i32.sub (local.get $c) (i32.const 1)))
+        (br $scale_more)))
+    (if (i32.lt_s (local.get $e) (i32.const 0))
+      (then (local.set $value (f64.div (local.get $value) (local.get $scale))))
+      (else (local.set $value (f64.mul (local.get $value) (local.get $scale)))))
+    (local.set $bits (i64.reinterpret_f64 (local.get $value)))
+    ;; An overflow starts from the largest finite value instead.
+    (if (i64.ge_u (local.get $bits) (i64.const 0x7ff0000000000000))
+      (then (local.set $bits (i64.const 0x7fefffffffffffff))))
+    (loop $adjust
+      (local.set $c (call $cmp_mid (local.get $bits)))
+      (if (i32.or (i32.gt_s (local.get $c) (i32.const 0))
+                  (i32.and (i32.eqz (local.get $c)) (i32.wrap_i64 (i64.and (local.get $bits) (i64.const 1)))))
+        (then
+          (local.set $bits (i64.add (local.get $bits) (i64.const 1)))
+          (br_if $adjust (i64.lt_u (local.get $bits) (i64.const 0x7ff0000000000000)))
+          (return (f64.reinterpret_i64 (local.get $bits)))))
+      (if (i64.ne (local.get $bits) (i64.const 0))
+        (then
+          (local.set $c (call $cmp_mid (i64.sub (local.get $bits) (i64.const 1))))
+          (if (i32.or (i32.lt_s (local.get $c) (i32.const 0))
+                      (i32.and (i32.eqz (local.get $c)) (i32.wrap_i64 (i64.and (local.get $bits) (i64.const 1)))))
+            (then
+              (local.set $bits (i64.sub (local.get $bits) (i64.const 1)))
+              (br $adjust))))))
+    (f64.reinterpret_i64 (local.get $bits)))
+
+  ;; The value of a hexadecimal digit, or -1.
+  (func $hex_digit (param $c i32) (result i32)
+    (if (call $is_digit (local.get $c))
+      (then (return (i32.sub (local.get $c) (i32.const 48)))))
+    (local.set $c (i32.or (local.get $c) (i32.const 32)))
+    (if (i32.lt_u (i32.sub (local.get $c) (i32.const 97)) (i32.const 6))
+      (then (return (i32.sub (local.get $c) (i32.const 87)))))
+    (i32.const -1))
+
+  ;; Consume hexadecimal digits, returning how many there were. The first
+  ;; 61 bits or so go into $hex_man, scaled by 2^$hex_exp. A nonzero digit
+  ;; past those sets $sticky.
+  (global $hex_man (mut i64) (i64.const 0))
+  (global $hex_exp (mut i32) (i32.const 0))
+  (func $hex_digits (param $fraction i32) (result i32)
+    (local $d i32) (local $n i32)
+    (block $done
+      (loop $more
+        (local.set $d (call $hex_digit (call $peek)))
+        (br_if $done (i32.lt_s (local.get $d) (i32.const 0)))
+        (if (i64.eqz (i64.shr_u (global.get $hex_man) (i64.const 60)))
+          (then
+            (global.set $hex_man (i64.add (i64.shl (global.get $hex_man) (i64.const 4))
+                                          (i64.extend_i32_u (local.get $d))))
+            (if (local.get $fraction)
+              (then (global.set $hex_exp (i32.sub (global.get $hex_exp) (i32.const 4))))))
+          (else
+            (if (local.get $d)
+              (then (global.set $sticky (i32.const 1))))
+            (if (i32.eqz (local.get $fraction))
+              (then (global.set $hex_exp (i32.add (global.get $hex_exp) (i32.const 4)))))))
+        (local.set $n (i32.add (local.get $n) (i32.const 1)))
+        (call $advance)
+        (br $more)))
+    (local.get $n))
+
+  ;; 2^e, for e from -1022 up.
+  (func $pow2 (param $e i32) (result f64)
+    (if (i32.gt_s (local.get $e) (i32.const 1023))
+      (then (return (f64.const inf))))
+    (f64.reinterpret_i64 (i64.shl (i64.extend_i32_u (i32.add (local.get $e) (i32.const 1023))) (i64.const 52))))
+
+  ;; $hex_man * 2^$hex_exp rounded to the nearest f64, ties to even, as
+  ;; strtod does. $sticky stands for the digits that didn't fit.
+  (func $hex_value (result f64)
+    (local $man i64) (local $exp i32) (local $lsb i32) (local $shift i32) (local $rem i64) (local $half i64)
+    (local.set $man (global.get $hex_man))
+    (local.set $exp (global.get $hex_exp))
+    (if (i64.eqz (local.get $man))
+      (then (return (f64.const 0))))
+    ;; The place of the last bit kept: 53 bits, or fewer below 2^-1022.
+    (local.set $lsb (i32.sub (i32.sub (i32.add (local.get $exp) (i32.const 64)) (i32.wrap_i64 (i64.clz (local.get $man))))
+                             (i32.const 53)))
+    (if (i32.lt_s (local.get $lsb) (i32.const -1074))
+      (then (local.set $lsb (i32.const -1074))))
+    (if (i32.gt_s (local.get $lsb) (local.get $exp))
+      (then
+        (local.set $shift (i32.sub (local.get $lsb) (local.get $exp)))
+        (if (i32.ge_u (local.get $shift) (i32.const 64))
+          (then
+            ;; Everything goes, so only more than half of 2^64 rounds up.
+            (local.set $man (i64.extend_i32_u
+              (i32.and (i32.eq (local.get $shift) (i32.const 64))
+                       (i32.or (i64.gt_u (local.get $man) (i64.const 0x8000000000000000))
+                               (i32.and (i64.eq (local.get $man) (i64.const 0x8000000000000000))
+                                        (global.get $sticky)))))))
+          (else
+            (local.set $rem (i64.and (local.get $man)
+                                     (i64.sub (i64.shl (i64.const 1) (i64.extend_i32_u (local.get $shift))) (i64.const 1))))
+            (local.set $half (i64.shl (i64.const 1) (i64.extend_i32_u (i32.sub (local.get $shift) (i32.const 1)))))
+            (local.set $man (i64.shr_u (local.get $man) (i64.extend_i32_u (local.get $shift))))
+            (if (i32.or (i64.gt_u (local.get $rem) (local.get $half))
+                        (i32.and (i64.eq (local.get $rem) (local.get $half))
+                                 (i32.or (global.get $sticky) (i32.wrap_i64 (i64.and (local.get $man) (i64.const 1))))))
+              (then (local.set $man (i64.add (local.get $man) (i64.const 1)))))))
+        (local.set $exp (local.get $lsb))))
+    ;; In two steps, since 2^exp on its own may not fit in an f64.
+    (f64.mul (f64.mul (f64.convert_i64_u (local.get $man))
+                      (call $pow2 (i32.div_s (local.get $exp) (i32.const 2))))
+             (call $pow2 (i32.sub (local.get $exp) (i32.div_s (local.get $exp) (i32.const 2))))))
+
+  ;; Whether the next letters are those packed into $letters, first letter
+  ;; lowest, ignoring case. Like glibc, this consumes the first letter that
+  ;; doesn't match.
+  (func $word (param $letters i64) (result i32)
+    (local $c i32)
+    (loop $letter
+      (local.set $c (call $peek))
+      (if (i32.ge_s (local.get $c) (i32.const 0))
+        (then (call $advance)))
+      (if (i32.ne (i32.or (local.get $c) (i32.const 32)) (i32.wrap_i64 (i64.and (local.get $letters) (i64.const 255))))
+        (then (return (i32.const 0))))
+      (local.set $letters (i64.shr_u (local.get $letters) (i64.const 8)))
+      (br_if $letter (i64.ne (local.get $letters) (i64.const 0))))
+    (i32.const 1))
+
+  ;; An exponent introduced by $letter (lower case), or 0 if there is none.
+  ;; The letter is consumed even if no digits follow.
+  (func $exponent (param $letter i32) (result i32)
+    (local $c i32) (local $neg i32) (local $e i32)
+    (if (i32.ne (i32.or (call $peek) (i32.const 32)) (local.get $letter))
+      (then (return (i32.const 0))))
+    (call $advance)
+    (local.set $c (call $peek))
+    (if (i32.or (i32.eq (local.get $c) (i32.const 43)) (i32.eq (local.get $c) (i32.const 45)))
+      (then
+        (local.set $neg (i32.eq (local.get $c) (i32.const 45)))
+        (call $advance)))
+    (block $done
+      (loop $more
+        (local.set $c (call $peek))
+        (br_if $done (i32.eqz (call $is_digit (local.get $c))))
+        (if (i32.lt_s (local.get $e) (i32.const 10000))
+          (then
+            (local.set $e (i32.add (i32.mul (local.get $e) (i32.const 10))
+                                   (i32.sub (local.get $c) (i32.const 48))))))
+        (call $advance)
+        (br $more)))
+    (select (i32.sub (i32.const 0) (local.get $e)) (local.get $e) (local.get $neg)))
+
+  ;; The equivalent of
+  ;;   if (0 == scanf("%lf", &x)) { x = 0; scanf("%*s"); }
+  ;; returning the new value of x.
+  (func $read_num (param $old f64) (result f64)
+    (local $c i32) (local $neg i32) (local $n i32) (local $e i32)
+    (local $value f64)
+    (block $fail
+      (loop $space
+        (local.set $c (call $peek))
+        (if (call $is_space (local.get $c))
+          (then (call $advance) (br $space))))
+      (if (i32.lt_s (local.get $c) (i32.const 0))
+        (then (return (local.get $old))))
+      (if (i32.or (i32.eq (local.get $c) (i32.const 43)) (i32.eq (local.get $c) (i32.const 45)))
+        (then
+          (local.set $neg (i32.eq (local.get $c) (i32.const 45)))
+          (call $advance)))
+      ;; "inf", "infinity" and "nan", in any case.
+      (local.set $c (i32.or (call $peek) (i32.const 32)))
+      (if (i32.eq (local.get $c) (i32.const 105))
+        (then
+          (br_if $fail (i32.eqz (call $word (i64.const 0x666e69))))
+          (if (i32.eq (i32.or (call $peek) (i32.const 32)) (i32.const 105))
+            (then (br_if $fail (i32.eqz (call $word (i64.const 0x7974696e69))))))
+          (return (select (f64.const -inf) (f64.const inf) (local.get $neg)))))
+      (if (i32.eq (local.get $c) (i32.const 110))
+        (then
+          (br_if $fail (i32.eqz (call $word (i64.const 0x6e616e))))
+          (return (select (f64.const -nan) (f64.const nan) (local.get $neg)))))
+      (global.set $mant (i64.const 0))
+      (global.set $exp10 (i32.const 0))
+      (global.set $ndig (i32.const 0))
+      (global.set $dexp (i32.const 0))
+      (global.set $sticky (i32.const 0))
+      (call $big_set (i32.const 4608) (i64.const 0))
+      (local.set $n (call $digits (i32.const 0)))
+      ;; A lone 0 followed by x starts a hexadecimal number.
+      (if (i32.and (i32.and (i32.eq (local.get $n) (i32.const 1)) (i32.eqz (global.get $ndig)))
+                   (i32.eq (i32.or (call $peek) (i32.const 32)) (i32.const 120)))
+        (then
+          (call $advance)
+          (global.set $hex_man (i64.const 0))
+          (global.set $hex_exp (i32.const 0))
+          (local.set $n (call $hex_digits (i32.const 0)))
+          (if (i32.eq (call $peek) (i32.const 46))
+            (then
+              (call $advance)
+              ;; "0x." on its own reads as 0.
+              (if (i32.eqz (i32.add (local.get $n) (call $hex_digits (i32.const 1))))
+                (then (return (select (f64.const -0) (f64.const 0) (local.get $neg))))))
+            (else (br_if $fail (i32.eqz (local.get $n)))))
+          (global.set $hex_exp (i32.add (global.get $hex_exp) (call $exponent (i32.const 112))))
+          (local.set $value (call $hex_value))
+          (return (select (f64.neg (local.get $value)) (local.get $value) (local.get $neg)))))
+      (if (i32.eq (call $peek) (i32.const 46))
+        (then
+          (call $advance)
+          (local.set $n (i32.add (local.get $n) (call $digits (i32.const 1))))))
+      (br_if $fail (i32.eqz (local.get $n)))
+      (local.set $e (call $exponent (i32.const 101)))
+      (global.set $exp10 (i32.add (global.get $exp10) (local.get $e)))
+      (global.set $dexp (i32.add (global.get $dexp) (local.get $e)))
+      ;; Digits dropped past the 800th only matter for breaking a tie, so a
+      ;; trailing 1 stands in for them.
+      (if (global.get $sticky)
+        (then
+          (call $big_mul (i32.const 4608) (i64.const 10) (i64.const 1))
+          (global.set $ndig (i32.add (global.get $ndig) (i32.const 1)))
+          (global.set $dexp (i32.sub (global.get $dexp) (i32.const 1)))))
+      (local.set $value (call $nearest))
+      (if (local.get $neg)
+        (then (local.set $value (f64.neg (local.get $value)))))
+      (return (local.get $value)))
+    ;; Not a number: skip the next word, like scanf("%*s").
+    (loop $space
+      (if (call $is_space (call $peek))
+        (then (call $advance) (br $space))))
+    (block $skipped
+      (loop $skip
+        (local.set $c (call $peek))
+        (br_if $skipped (i32.lt_s (local.get $c) (i32.const 0)))
+        (br_if $skipped (call $is_space (local.get $c)))
+        (call $advance)
+        (br $skip)))
+    (f64.const 0))
+"#;
+
+pub struct WatEmitter {
+    passes: Passes,
+    header: String,
+    code: String,
+    /// Bytes of every string literal, laid out from DATA_START
+    data: Vec<u8>,
+    number_type: NumberType,
+}
+
+impl WatEmitter {
+    pub fn new(passes: Passes) -> Self {
+        Self {
+            passes,
+            header: String::new(),
+            code: String::new(),
+            // The newline printed by PRINTLN lives at DATA_START.
+            data: vec![b'\n'],
+            number_type: NumberType::F32,
+        }
+    }
+
+    /// The WebAssembly type of every value
+    fn ty(&self) -> &'static str {
+        match self.number_type {
+            NumberType::F32 => "f32",
+            NumberType::F64 => "f64",
+        }
+    }
+
+    fn instr(&mut self, code: &str) {
+        self.emit("    ");
+        self.emit_line(code);
+    }
+
+    /// Place a string in linear memory and return its address
+    fn string_constant(&mut self, text: &str) -> usize {
+        let addr = DATA_START + self.data.len();
+        self.data.extend_from_slice(text.as_bytes());
+        addr
+    }
+
+    fn instruction(&mut self, instr: &Instr) {
+        match instr {
+            Instr::PrintStr { text, newline } => {
+                let mut text = text.clone();
+                if *newline {
+                    text.push('\n');
+                }
+                let addr = self.string_constant(&text);
+                self.instr(&format!("(call $write (i32.const {}) (i32.const {}))", addr, text.len()));
+            }
+            Instr::Print { value, newline } => {
+                self.operand(value);
+                if self.number_type == NumberType::F32 {
+                    self.instr("f64.promote_f32");
+                }
+                self.instr("call $print_num");
+                if *newline {
+                    self.instr(&format!("(call $write (i32.const {}) (i32.const 1))", DATA_START));
+                }
+            }
+            Instr::Copy { dest, src } => {
+                self.operand(src);
+                self.instr(&format!("local.set {}", local(dest)));
+            }
+            Instr::Neg { dest, src } => {
+                self.operand(src);
+                self.instr(&format!("{}.neg", self.ty()));
+                self.instr(&format!("local.set {}", local(dest)));
+            }
+            Instr::Binary { dest, op, left, right } => {
+                self.operand(left);
+                self.operand(right);
+                self.instr(&format!("{}.{}", self.ty(), binary_instr(*op)));
+                if op.is_comparison() {
+                    self.instr(&format!("{}.convert_i32_u", self.ty()));
+                }
+                self.instr(&format!("local.set {}", local(dest)));
+            }
+            Instr::Input(name) => {
+                self.instr(&format!("local.get $v_{}", name));
+                if self.number_type == NumberType::F32 {
+                    self.instr("f64.promote_f32");
+                    self.instr("call $read_num");
+                    self.instr("f32.demote_f64");
+                }
+                else {
+                    self.instr("call $read_num");
+                }
+                self.instr(&format!("local.set $v_{}", name));
+            }
+        }
+    }
+
+    /// Push the value of an operand
+    fn operand(&mut self, o: &Operand) {
+        match o {
+            Operand::Const(c) => self.instr(&format!("{}.const {}", self.ty(), self.number_type.text(*c))),
+            _ => self.instr(&format!("local.get {}", local(o))),
+        }
+    }
+
+    /// Arbitrary GOTOs have no structured equivalent, so every basic block
+    /// becomes a case of a br_table dispatcher inside one big loop:
+    ///
+    /// ```text
+    /// loop $dispatch
+    ///   block $b1
+    ///     block $b0
+    ///       br_table $b0 $b1 (state)
+    ///     end
+    ///     block 0 code (falls into block 1)
+    ///   end
+    ///   block 1 code
+    /// end
+    /// ```
+    fn dispatcher(&mut self, ir: &Ir) {
+        let n = ir.blocks.len();
+        self.instr("loop $dispatch");
+        for i in (0..n).rev() {
+            self.instr(&format!("block $b{}", i));
+        }
+        self.instr("local.get $state");
+        let targets: Vec<String> = (0..n).map(|i| format!("$b{}", i)).collect();
+        self.instr(&format!("br_table {}", targets.join(" ")));
+        for (i, block) in ir.blocks.iter().enumerate() {
+            self.instr("end");
+            self.emit_line(&format!("    ;; block {}", i));
+            for instr in block.instrs.iter() {
+                self.instruction(instr);
+            }
+            match &block.terminator {
+                Terminator::Jump(t) if *t == i + 1 => {}
+                Terminator::Jump(t) => {
+                    self.instr(&format!("(local.set $state (i32.const {}))", t));
+                    self.instr("br $dispatch");
+                }
+                Terminator::Branch { condition, then, otherwise } => {
+                    self.instr(&format!("i32.const {}", then));
+                    self.instr(&format!("i32.const {}", otherwise));
+                    self.operand(condition);
+                    self.instr(&format!("{}.const 0", self.ty()));
+                    self.instr(&format!("{}.ne", self.ty()));
+                    self.instr("select");
+                    self.instr("local.set $state");
+                    self.instr("br $dispatch");
+                }
+                Terminator::Return => self.instr("return"),
+            }
+        }
+        self.instr("end");
+    }
+}
+
+/// The local holding a variable or temporary
+fn local(o: &Operand) -> String {
+    match o {
+        Operand::Var(v) => format!("$v_{}", v),
+        Operand::Temp(t) => format!("$t{}", t),
+        Operand::Const(_) => unreachable!(),
+    }
+}
+
+/// The instruction for an operator, without the type
+fn binary_instr(op: BinOp) -> &'static str {
+    match op {
+        BinOp::Add => "add",
+        BinOp::Sub => "sub",
+        BinOp::Mul => "mul",
+        BinOp::Div => "div",
+        BinOp::EqEq => "eq",
+        BinOp::NotEq => "ne",
+        BinOp::Lt => "lt",
+        BinOp::LtEq => "le",
+        BinOp::Gt => "gt",
+        BinOp::GtEq => "ge",
+    }
+}
+
+/// Escape bytes for a WAT string literal
+fn escape(bytes: &[u8]) -> String {
+    let mut s = String::new();
+    for &b in bytes {
+        if b == b'"' || b == b'\\' || !(0x20..0x7f).contains(&b) {
+            s.push_str(&format!("\\{:02x}", b));
+        }
+        else {
+            s.push(b as char);
+        }
+    }
+    s
+}
+
+impl Emitter for WatEmitter {
+    fn emit(&mut self, code: &str) {
+        self.code.push_str(code);
+    }
+
+    fn emit_line(&mut self, code: &str) {
+        self.emit(code);
+        self.code.push('\n');
+    }
+
+    fn header_line(&mut self, code: &str) {
+        self.header.push_str(code);
+        self.header.push('\n');
+    }
+
+    fn write(&self, out: &mut dyn Write) -> io::Result<()> {
+        out.write_all(self.header.as_bytes())?;
+        out.write_all(self.code.as_bytes())
+    }
+
+    fn program(&mut self, program: &Program) {
+        let ir = opt::lower(program, &self.passes);
+        self.number_type = ir.number_type;
+        self.dispatcher(&ir);
+        self.emit_line("  )");
+        self.emit_line(")");
+
+        let pages = (DATA_START + self.data.len()) / 65536 + 1;
+        self.header_line("(module");
+        self.header_line("  (import \"wasi_snapshot_preview1\" \"fd_write\" (func $fd_write (param i32 i32 i32 i32) (result i32)))");
+        self.header_line("  (import \"wasi_snapshot_preview1\" \"fd_read\" (func $fd_read (param i32 i32 i32 i32) (result i32)))");
+        self.header_line(&format!("  (memory (export \"memory\") {})", pages));
+        // Spellings used by $print_num for infinities and NaNs.
+        self.header_line("  (data (i32.const 160) \"-naninf\")");
+        let data = format!("  (data (i32.const {}) \"{}\")", DATA_START, escape(&self.data));
+        self.header_line(&data);
+        self.header_line(RUNTIME);
+        self.header_line("  (func $main (export \"_start\")");
+        self.header_line("    (local $state i32)");
+        for v in ir.variables.iter() {
+            self.header_line(&format!("    (local $v_{} {})", v, self.ty()));
+        }
+        for t in 1..=ir.num_temps {
+            self.header_line(&format!("    (local $t{} {})", t, self.ty()));
+        }
+    }
+}
@@ -0,0 +1,61 @@
+// cemitter.rs
+// The C the default target produces, and what it does when built.
+
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use ttrust::{compile_str, CompileOptions, NumberType};
+
+#[test]
+fn variables_start_at_zero() {
+    // INPUT at the end of the input leaves x as it was.
+    let code = compile_str("INPUT x\nPRINTLN x\n", &CompileOptions::new()).unwrap().code;
+    assert!(code.contains("float v_x = 0.0f;"), "{}", code);
+}
+
+#[test]
+fn f64_uses_double() {
+    let options = CompileOptions::new().number_type(NumberType::F64);
+    let code = compile_str("INPUT x\nPRINTLN x + 0.1\n", &options).unwrap().code;
+    assert!(code.contains("double v_x = 0.0;"), "{}", code);
+    assert!(code.contains("\"%lf\""), "{}", code);
+    assert!(code.contains("0.1)"), "{}", code);
+}
+
+/// Build the C for `source` with cc and run it on `input`. None if cc
+/// isn't installed.
+fn run_c(source: &str, input: &str, name: &str) -> Option<String> {
+    if Command::new("cc").arg("--version").stdout(Stdio::null()).status().is_err() {
+        eprintln!("cc not found; skipping");
+        return None;
+    }
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("cemitter");
+    fs::create_dir_all(&dir).unwrap();
+    let (c, exe) = (dir.join(name).with_extension("c"), dir.join(name));
+    fs::write(&c, compile_str(source, &CompileOptions::new()).unwrap().code).unwrap();
+    let status = Command::new("cc").arg("-o").arg(&exe).arg(&c).status().unwrap();
+    assert!(status.success(), "cc failed on {}", c.display());
+    let mut child = Command::new(&exe).stdin(Stdio::piped()).stdout(Stdio::piped()).spawn().unwrap();
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    Some(String::from_utf8(output.stdout).unwrap())
+}
+
+#[test]
+fn c_names_can_be_teeny_names() {
+    let source = "LET int = 0\nLET main = 2\nLET printf = 3\nINPUT float\nLABEL return\nLET int = int + 1\n\
+                  IF int < 3 THEN\nGOTO return\nENDIF\nPRINTLN int * main + printf + float\n";
+    if let Some(output) = run_c(source, "0.5\n", "c-names") {
+        assert_eq!(output, "9.50\n");
+    }
+}
+
+#[test]
+fn strings_are_printed_as_written() {
+    let source = "PRINTLN \"100% done\\tok %d %s\"\nPRINTLN \"say \\\"hi\\\" \\\\n\\ncaf\\u{e9} \\u{1F600}\"\n";
+    if let Some(output) = run_c(source, "", "strings") {
+        assert_eq!(output, "100% done\tok %d %s\nsay \"hi\" \\n\ncaf\u{e9} \u{1F600}\n");
+    }
+}
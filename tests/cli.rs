@@ -0,0 +1,115 @@
+// cli.rs
+// The ttrust command itself: where it writes and what it passes through.
+
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+fn ttrust(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_ttrust")).args(args).output().unwrap()
+}
+
+/// A scratch directory for one test
+fn scratch(name: &str) -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("cli").join(name);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn dash_writes_to_stdout() {
+    let dir = scratch("stdout");
+    let source = dir.join("hello.teeny");
+    fs::write(&source, "PRINTLN \"hello\"\n").unwrap();
+    let output = ttrust(&["-o", "-", source.to_str().unwrap()]);
+    assert!(output.status.success());
+    let code = String::from_utf8(output.stdout).unwrap();
+    assert!(code.contains("int main(void)") && code.contains("hello"), "{}", code);
+    // Nothing is written next to the source or in the current directory.
+    assert!(!dir.join("out.c").exists() && !dir.join("-").exists());
+    assert!(!PathBuf::from("-").exists());
+}
+
+#[test]
+fn failed_compile_leaves_the_output_alone() {
+    let dir = scratch("failed");
+    let (source, out) = (dir.join("bad.teeny"), dir.join("bad.c"));
+    fs::write(&source, "PRINTLN x\n").unwrap();
+    fs::write(&out, "previous output\n").unwrap();
+    let output = ttrust(&["-o", out.to_str().unwrap(), source.to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("Compiler error: Variable 'x'"));
+    assert_eq!(fs::read_to_string(&out).unwrap(), "previous output\n");
+    // No temporary file is left behind either.
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+}
+
+#[test]
+fn run_passes_stdin_and_stdout_through() {
+    if Command::new("cc").arg("--version").output().is_err() {
+        eprintln!("cc not found; skipping");
+        return;
+    }
+    let dir = scratch("run");
+    let source = dir.join("double.teeny");
+    fs::write(&source, "INPUT x\nPRINTLN x * 2\nINPUT x\nPRINTLN x * 2\n").unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_ttrust"))
+        .args(["run", source.to_str().unwrap()])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"1.5\n4\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "3.00\n8.00\n");
+}
+
+/// A stand-in for the C compiler that "builds" a shell script
+#[cfg(unix)]
+fn fake_cc(dir: &std::path::Path, program: &str) -> PathBuf {
+    use std::os::unix::fs::PermissionsExt;
+    let cc = dir.join("fake-cc");
+    let script = format!("#!/bin/sh\nwhile [ \"$1\" != -o ]; do shift; done\nprintf '#!/bin/sh\\n{}\\n' > \"$2\"\nchmod +x \"$2\"\n", program);
+    fs::write(&cc, script).unwrap();
+    fs::set_permissions(&cc, fs::Permissions::from_mode(0o755)).unwrap();
+    cc
+}
+
+#[cfg(unix)]
+#[test]
+fn run_exits_with_the_program_exit_code() {
+    let dir = scratch("exit");
+    let source = dir.join("prog.teeny");
+    fs::write(&source, "PRINTLN 1\n").unwrap();
+    for (program, code) in [("exit 3", 3), ("kill -TERM $$", 128 + 15)] {
+        let status = Command::new(env!("CARGO_BIN_EXE_ttrust"))
+            .args(["run", source.to_str().unwrap()])
+            .env("CC", fake_cc(&dir, program))
+            .status()
+            .unwrap();
+        assert_eq!(status.code(), Some(code), "{}", program);
+    }
+}
+
+#[test]
+fn c_compiler_errors_point_at_the_teeny_source() {
+    if Command::new("cc").arg("--version").output().is_err() {
+        eprintln!("cc not found; skipping");
+        return;
+    }
+    let dir = scratch("line");
+    let source = dir.join("count.teeny");
+    fs::write(&source, "INPUT i\n\nIF i == 0 THEN\n    PRINTLN i\nENDIF\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_ttrust"))
+        .args(["build", "-A", "float-equality", "-o", dir.join("count").to_str().unwrap(), source.to_str().unwrap()])
+        .env("CC", "cc")
+        .env("CFLAGS", "-Wfloat-equal -Werror")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("count.teeny:3:"), "{}", stderr);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("The C compiler failed on"));
+}
@@ -0,0 +1,54 @@
+// cst.rs
+// The concrete syntax tree has to give back the source byte for byte.
+
+use std::fs;
+use ttrust::{cst, Dialect};
+
+/// Parse, print and compare, and check that the tokens cover the source
+/// without gaps or overlaps
+fn round_trip(source: &str, dialect: Dialect) {
+    let tree = cst::parse(source, dialect).unwrap();
+    assert_eq!(tree.to_string(), source);
+    let mut end = 0;
+    for t in tree.tokens() {
+        assert_eq!(t.span.start, end, "gap or overlap before {:?}", t);
+        assert_eq!(&source[t.span.start..t.span.end], t.text);
+        end = t.span.end;
+    }
+    assert_eq!(end, source.len());
+}
+
+#[test]
+fn examples_round_trip() {
+    for entry in fs::read_dir("examples").unwrap() {
+        let path = entry.unwrap().path();
+        round_trip(&fs::read_to_string(&path).unwrap(), Dialect::Strict);
+    }
+}
+
+#[test]
+fn odd_layout_round_trips() {
+    let source = "\
+  # leading comment\r
+## Docs for top.\r
+\r
+## More docs.\r
+LABEL   top\t# trailing\r
+LET a=1+-2*3 - 0x1F\r
+IF a>=0 THEN\r
+# first in block\r
+\tPRINT \"it's #not a comment\"  ## not docs\r
+    WHILE a<3 REPEAT\r
+LET a = a + 1_000\r
+  ENDWHILE\r
+ENDIF\r
+\r
+\r
+GOTO top  # no newline at the end";
+    round_trip(source, Dialect::Strict);
+}
+
+#[test]
+fn lower_case_keywords_round_trip() {
+    round_trip("let a = 1\nif a == 1 then\n  println a\nendif\n", Dialect::CaseInsensitive);
+}
@@ -0,0 +1,38 @@
+// dce.rs
+// Dead code elimination, checked through the warnings it gives.
+
+use ttrust::{compile_str, CompileOptions};
+
+/// The warning messages from compiling `source` to C
+fn warnings(source: &str) -> Vec<String> {
+    let output = compile_str(source, &CompileOptions::new()).unwrap();
+    output.warnings.into_iter().map(|w| w.message).collect()
+}
+
+#[test]
+fn label_only_jumped_to_from_dead_code_is_dead() {
+    let source = "GOTO done\nLABEL again\nPRINTLN 1\nGOTO again\nLABEL done\n";
+    assert_eq!(warnings(source), ["Unreachable code after GOTO done removed. [unreachable-code]"]);
+}
+
+#[test]
+fn label_reached_by_falling_through_is_unused() {
+    assert_eq!(warnings("LABEL top\nPRINTLN 1\n"), ["Label 'top' is never used. [unused-label]"]);
+}
+
+#[test]
+fn live_labels_make_more_code_live() {
+    // b is only reachable from the code after a, and c from the code
+    // after b.
+    let source = "LET n = 0\nGOTO a\nLABEL b\nPRINTLN n\nGOTO c\nLABEL a\nLET n = n + 1\nGOTO b\nLABEL c\n";
+    assert_eq!(warnings(source), Vec::<String>::new());
+}
+
+#[test]
+fn loop_entered_by_goto_keeps_its_top() {
+    // The PRINT is reached the second time round the loop.
+    let source = "LET i = 0\nGOTO middle\nWHILE i < 2 REPEAT\nPRINT \"top\"\nLABEL middle\nLET i = i + 1\nENDWHILE\n";
+    assert_eq!(warnings(source), Vec::<String>::new());
+    let code = compile_str(source, &CompileOptions::new()).unwrap().code;
+    assert!(code.contains("\"top\""), "{}", code);
+}
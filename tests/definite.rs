@@ -0,0 +1,61 @@
+// definite.rs
+// Reads that may come before an assignment, and where they are reported.
+
+use ttrust::{compile_str, CompileOptions};
+
+/// The error message for source that should fail to compile
+fn error(source: &str) -> String {
+    match compile_str(source, &CompileOptions::new()) {
+        Ok(_) => panic!("compiled, but should have failed:\n{}", source),
+        Err(d) => d.error.message,
+    }
+}
+
+#[test]
+fn assignment_in_a_while_body_may_not_run() {
+    let source = "INPUT n\nWHILE n > 0 REPEAT\n    LET x = 1\n    LET n = n - 1\nENDWHILE\nPRINTLN x\n";
+    assert_eq!(
+        error(source),
+        "Variable 'x' is possibly used before assignment at line 6, column 9, on the path: 'n > 0' is false"
+    );
+}
+
+#[test]
+fn goto_can_jump_around_a_let() {
+    let source = "GOTO skip\nLET y = 2\nLABEL skip\nPRINTLN 1 + y\n";
+    assert_eq!(
+        error(source),
+        "Variable 'y' is possibly used before assignment at line 4, column 13, on the path: LABEL skip"
+    );
+}
+
+#[test]
+fn assignment_on_one_branch_of_an_if() {
+    let source = "INPUT n\nIF n > 0 THEN\n    LET z = 1\nENDIF\nPRINTLN n + z * z\n";
+    assert_eq!(
+        error(source),
+        "Variable 'z' is possibly used before assignment at line 5, column 13, on the path: 'n > 0' is false"
+    );
+}
+
+#[test]
+fn loop_condition_is_reported_where_it_reads() {
+    let source = "INPUT q\nIF q > 0 THEN\n    LET b = 1\nENDIF\nWHILE b > 0 REPEAT\n    LET b = 0\nENDWHILE\n";
+    assert!(error(source).contains("'b' is possibly used before assignment at line 5, column 7,"));
+}
+
+#[test]
+fn reads_before_any_assignment() {
+    assert_eq!(error("PRINTLN q\n"), "Variable 'q' is used but never assigned at line 1, column 9");
+    assert_eq!(error("LET a = 1 + a\n"), "Variable 'a' is used before assignment at line 1, column 13");
+}
+
+#[test]
+fn assigned_on_every_path() {
+    // Both ways past the IF assign x before the loop reads it.
+    let source = "INPUT n\nIF n > 0 THEN\n    LET x = 1\n    GOTO done\nENDIF\nLET x = 2\nLABEL done\n\
+                  WHILE n > 0 REPEAT\n    LET n = n - x\nENDWHILE\n";
+    if let Err(d) = compile_str(source, &CompileOptions::new()) {
+        panic!("failed to compile:\n{}", d);
+    }
+}
@@ -0,0 +1,33 @@
+// fold.rs
+// The -O1 simplifications, checked in the C they produce.
+
+use ttrust::{compile_str, CompileOptions};
+
+fn c_at_o1(source: &str) -> String {
+    compile_str(source, &CompileOptions::new().opt_level(1)).unwrap().code
+}
+
+#[test]
+fn constants_are_folded() {
+    assert!(c_at_o1("LET a = 1 + 2 * 3\n").contains("v_a = 7.0f;"));
+}
+
+#[test]
+fn adding_zero_is_kept_for_negative_zero() {
+    let code = c_at_o1("LET b = 0\nPRINTLN 0 + -b\nPRINTLN b + 0\n");
+    assert!(code.contains("0.0f + -v_b"), "{}", code);
+    assert!(code.contains("v_b + 0.0f"), "{}", code);
+}
+
+#[test]
+fn subtracting_zero_is_removed() {
+    let code = c_at_o1("LET b = 0\nPRINTLN b - 0\n");
+    assert!(code.contains("(float)(v_b));"), "{}", code);
+}
+
+#[test]
+fn subtracting_a_variable_from_itself_is_kept() {
+    // inf - inf is NaN, not 0.
+    let code = c_at_o1("INPUT x\nLET y = x - x\nPRINTLN y\n");
+    assert!(code.contains("v_y = v_x - v_x;"), "{}", code);
+}
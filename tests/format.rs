@@ -0,0 +1,29 @@
+// format.rs
+// `ttrust fmt`, through ttrust::format.
+
+use ttrust::{format, Dialect};
+
+fn fmt(source: &str) -> String {
+    format::format(source, Dialect::Strict).unwrap()
+}
+
+#[test]
+fn formatted_source_is_unchanged() {
+    let source = "\
+LET a = 1
+IF a > 0 THEN
+    # first
+    WHILE a < 3 REPEAT
+        # inner
+        LET a = a + 1
+    ENDWHILE
+ENDIF
+";
+    assert_eq!(fmt(source), source);
+}
+
+#[test]
+fn comments_in_a_block_are_indented_with_it() {
+    let source = "IF a>0 THEN\n# first\n  PRINT a # after\nENDIF\n";
+    assert_eq!(fmt(source), "IF a > 0 THEN\n    # first\n    PRINT a # after\nENDIF\n");
+}
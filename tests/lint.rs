@@ -0,0 +1,117 @@
+// lint.rs
+// Each lint, its level and where it points, checked through compile_str.
+
+use ttrust::{compile_str, CompileOptions, Diagnostic, Level};
+
+fn warnings_with(source: &str, options: &CompileOptions) -> Vec<Diagnostic> {
+    match compile_str(source, options) {
+        Ok(output) => output.warnings,
+        Err(d) => panic!("failed to compile:\n{}\n{}", source, d),
+    }
+}
+
+/// Each warning as the command line prints it
+fn warnings(source: &str) -> Vec<String> {
+    warnings_with(source, &CompileOptions::new()).iter().map(|w| w.to_string()).collect()
+}
+
+#[test]
+fn unused_variable() {
+    assert_eq!(
+        warnings("PRINTLN 1\nLET a = 1\nLET a = 2\n"),
+        ["Compiler warning at line 2, column 1: Variable 'a' is never read. [unused-variable]"]
+    );
+}
+
+#[test]
+fn each_unused_assignment() {
+    assert_eq!(
+        warnings("LET a = 1\nLET a = 2\nLET a = 3\nPRINTLN a\n"),
+        [
+            "Compiler warning at line 1, column 1: Variable 'a' is assigned a value that is never read. [unused-assignment]",
+            "Compiler warning at line 2, column 1: Variable 'a' is assigned a value that is never read. [unused-assignment]",
+        ]
+    );
+}
+
+#[test]
+fn unused_label() {
+    assert_eq!(
+        warnings("PRINTLN 1\n  LABEL top\n"),
+        ["Compiler warning at line 2, column 3: Label 'top' is never used. [unused-label]"]
+    );
+}
+
+#[test]
+fn unreachable_code() {
+    assert_eq!(
+        warnings("LABEL top\nGOTO top\nPRINTLN 1\n"),
+        ["Compiler warning at line 3, column 1: Unreachable code after GOTO top removed. [unreachable-code]"]
+    );
+}
+
+#[test]
+fn empty_loop() {
+    assert_eq!(
+        warnings("INPUT n\nWHILE n > 0 REPEAT\nENDWHILE\n"),
+        ["Compiler warning at line 2, column 1: WHILE n > 0 has an empty body. [empty-loop]"]
+    );
+}
+
+#[test]
+fn constant_condition() {
+    assert_eq!(
+        warnings("IF 1 < 2 THEN\nPRINTLN 1\nENDIF\n"),
+        ["Compiler warning at line 1, column 4: Condition '1 < 2' is always true. [constant-condition]"]
+    );
+}
+
+#[test]
+fn float_equality_warns_by_default() {
+    assert_eq!(
+        warnings("INPUT i\nIF i == 0 THEN\nPRINTLN i\nENDIF\n"),
+        ["Compiler warning at line 2, column 4: Comparison 'i == 0' tests floats for exact equality. [float-equality]"]
+    );
+    // A fraction is clearly meant as a float.
+    assert_eq!(warnings("INPUT i\nIF i == 0.5 THEN\nPRINTLN i\nENDIF\n"), Vec::<String>::new());
+}
+
+#[test]
+fn levels_from_the_command_line() {
+    let source = "INPUT i\nLET u = i\nIF i == 0 THEN\nPRINTLN i\nENDIF\n";
+    let allowed = CompileOptions::new().lint("float-equality", Level::Allow);
+    let names: Vec<_> = warnings_with(source, &allowed).into_iter().map(|w| w.message).collect();
+    assert_eq!(names, ["Variable 'u' is never read. [unused-variable]"]);
+    // -W after -A turns it back on.
+    let warned = allowed.clone().lint("float-equality", Level::Warn);
+    assert_eq!(warnings_with(source, &warned).len(), 2);
+    assert_eq!(warnings_with(source, &CompileOptions::new().lint("warnings", Level::Allow)), []);
+
+    let denied = CompileOptions::new().lint("unused-variable", Level::Deny);
+    let d = compile_str(source, &denied).unwrap_err();
+    assert_eq!(d.error.to_string(), "Compiler error at line 2, column 1: Variable 'u' is never read. [unused-variable]");
+    // Warnings found before the error come back with it.
+    assert_eq!(d.warnings.len(), 1);
+}
+
+#[test]
+fn deny_warnings_rejects_any_warning() {
+    let options = CompileOptions::new().lint("warnings", Level::Deny);
+    let d = compile_str("LET a = 1\n", &options).unwrap_err();
+    assert_eq!(d.error.message, "Variable 'a' is never read. [unused-variable]");
+    assert!(compile_str("LET a = 1\nPRINTLN a\n", &options).is_ok());
+    // A lint that is allowed stays allowed.
+    let options = options.lint("unused-variable", Level::Allow);
+    assert!(compile_str("LET a = 1\n", &options).is_ok());
+}
+
+#[test]
+fn pragma_allows_lints_for_the_file() {
+    let source = "# ttrust: allow(unused-variable, unused-label)\nLET a = 1\nLABEL top\n";
+    assert_eq!(warnings(source), Vec::<String>::new());
+    // Whatever the command line says
+    let denied = CompileOptions::new().lint("warnings", Level::Deny).lint("unused-label", Level::Deny);
+    assert_eq!(warnings_with(source, &denied), []);
+    let source = "LET a = 1\n# ttrust: allow(empty-loop)\nWHILE a > 0 REPEAT\nENDWHILE\n";
+    assert_eq!(warnings(source), Vec::<String>::new());
+}
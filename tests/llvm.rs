@@ -0,0 +1,52 @@
+// llvm.rs
+// The LLVM IR for the examples has to pass `opt -verify`. Skipped when
+// opt isn't installed.
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use ttrust::{compile_str, CompileOptions, Target};
+
+/// The major version of the installed opt, if there is one
+fn opt_version() -> Option<u32> {
+    let output = Command::new("opt").arg("--version").output().ok()?;
+    let text = String::from_utf8_lossy(&output.stdout);
+    let version = text.split("LLVM version ").nth(1)?;
+    version.split('.').next()?.trim().parse().ok()
+}
+
+#[test]
+fn examples_pass_opt_verify() {
+    let version = match opt_version() {
+        Some(v) => v,
+        None => {
+            eprintln!("opt not found; skipping");
+            return;
+        }
+    };
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    for entry in fs::read_dir("examples").unwrap() {
+        let path = entry.unwrap().path();
+        let source = fs::read_to_string(&path).unwrap();
+        for level in 0..=2 {
+            let options = CompileOptions::new().target(Target::Llvm).opt_level(level);
+            let code = compile_str(&source, &options).unwrap().code;
+            let ll = dir.join(format!("{}-O{}.ll", path.file_stem().unwrap().to_str().unwrap(), level));
+            fs::write(&ll, code).unwrap();
+            let mut opt = Command::new("opt");
+            // The IR uses `ptr`, which LLVM 14 and earlier only read with
+            // opaque pointers turned on.
+            if version < 15 {
+                opt.arg("-opaque-pointers");
+            }
+            let output = opt.arg("-verify").arg("-disable-output").arg(&ll).output().unwrap();
+            assert!(
+                output.status.success(),
+                "{} at -O{}:\n{}",
+                path.display(),
+                level,
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+    }
+}
@@ -0,0 +1,146 @@
+// parse.rs
+// What the lexer and parser accept and reject, checked through compile_str.
+
+use ttrust::{compile_str, CompileOptions, Dialect, EmitKind, NumberType};
+
+/// The error message for source that should fail to compile
+fn error(source: &str) -> String {
+    match compile_str(source, &CompileOptions::new()) {
+        Ok(_) => panic!("compiled, but should have failed:\n{}", source),
+        Err(d) => d.error.message,
+    }
+}
+
+fn compiles(source: &str) {
+    if let Err(d) = compile_str(source, &CompileOptions::new()) {
+        panic!("failed to compile:\n{}\n{}", source, d);
+    }
+}
+
+#[test]
+fn double_hash_after_a_statement_is_a_comment() {
+    compiles("PRINT 1 ## note\n");
+}
+
+#[test]
+fn double_hash_without_a_label_is_a_comment() {
+    compiles("## note\nPRINT 1\n");
+    compiles("PRINT 1\n## note\n");
+}
+
+#[test]
+fn doc_comment_is_attached_to_the_label_after_it() {
+    let options = CompileOptions::new().emit(EmitKind::AstJson);
+    let source = "## Start here.\n# not part of it\n\n## Second line.\nLABEL top\nGOTO top\n";
+    let json = compile_str(source, &options).unwrap().code;
+    assert!(json.contains(r#""doc":"Start here.\nSecond line.""#), "{}", json);
+}
+
+#[test]
+fn doc_comments_before_a_block_comment_and_label() {
+    let options = CompileOptions::new().emit(EmitKind::AstJson);
+    let source = "## Docs.\n#[ a\n## inside ]# LABEL top\nGOTO top\n";
+    let json = compile_str(source, &options).unwrap().code;
+    assert!(json.contains(r#""doc":"Docs.""#), "{}", json);
+    // An unterminated block comment is reported, not taken for docs.
+    assert!(error("## Docs.\n#[ LABEL top\n").starts_with("Unterminated block comment"));
+}
+
+#[test]
+fn long_runs_of_double_hash_lines_are_quick() {
+    // Each line used to look ahead through all of the rest.
+    let start = std::time::Instant::now();
+    let banner = "## banner\n".repeat(50000);
+    compiles(&format!("{}PRINT 1\n", banner));
+    compiles(&format!("{}LABEL top\nGOTO top\n", banner));
+    assert!(start.elapsed().as_secs() < 10, "{:?}", start.elapsed());
+}
+
+#[test]
+fn unknown_statement_is_rejected() {
+    assert!(error("FOO 1\n").contains("Invalid statement"));
+}
+
+#[test]
+fn expressions_have_spans() {
+    let options = CompileOptions::new().emit(EmitKind::AstJson);
+    let json = compile_str("LET a = 2\nLET b = 1 + -a * 2\n", &options).unwrap().code;
+    assert!(json.starts_with(r#"{"format":"ttrust-ast","version":2,"#), "{}", json);
+    // The last 2, and -a * 2 from the minus to the 2
+    assert!(json.contains(r#""value":"2","span":{"start":27,"end":28,"line":2,"column":18}"#), "{}", json);
+    assert!(json.contains(r#""span":{"start":22,"end":28,"line":2,"column":13}"#), "{}", json);
+}
+
+#[test]
+fn parser_errors_give_the_line_and_column() {
+    assert_eq!(error("LET a = 1\nLET = 2\n"), "Expected Ident, got Eq at line 2, column 5");
+    assert_eq!(error("PRINT 1\nGOTO nowhere\n"), "Attempted to GOTO to undeclared label 'nowhere' at line 2, column 6");
+    assert_eq!(error("LABEL a\n\nLABEL a\n"), "Label already declared 'a' at line 3, column 7");
+    assert_eq!(error("IF 1 THEN\nENDIF\n"), "Expected comparison operator, got Then at line 1, column 6");
+    assert!(error("PRINT 1\nLET a = 1 +\n").ends_with("at line 2, column 12"));
+}
+
+#[test]
+fn unprintable_characters_are_escaped_in_errors() {
+    assert_eq!(error("PRINT"), "Unexpected token '\\n' at line 1, column 6");
+    assert_eq!(error("IF a !\n"), "Expected !=, got '!' followed by '\\n' at line 1, column 6");
+    assert_eq!(error("LET a = \u{7}\n"), "Unknown token: '\\u{7}' at line 1, column 9");
+}
+
+#[test]
+fn backslash_at_the_end_of_a_line_leaves_the_string_unterminated() {
+    assert_eq!(error("PRINT \"a\\\n"), "Unterminated string starting at line 1, column 7");
+}
+
+#[test]
+fn bad_escapes_are_rejected() {
+    assert_eq!(error("PRINT \"\\q\"\n"), "Unknown escape sequence '\\q' in string at line 1, column 9");
+    assert!(error("PRINT \"\\u{}\"\n").starts_with("Expected 1 to 6 hex digits"));
+    assert!(error("PRINT \"\\u{41\"\n").starts_with("Expected 1 to 6 hex digits"));
+    assert!(error("PRINT \"\\u{1234567}\"\n").starts_with("Expected 1 to 6 hex digits"));
+    assert!(error("PRINT \"\\u{110000}\"\n").starts_with("\\u{110000} is not a Unicode character"));
+    assert!(error("PRINT \"\\u{d800}\"\n").starts_with("\\u{d800} is not a Unicode character"));
+    assert!(error("PRINT \"\\u{0}\"\n").starts_with("Strings can't contain \\u{0}"));
+}
+
+#[test]
+fn literals_that_round_to_infinity_are_rejected() {
+    assert_eq!(error("PRINT 1e39\n"), "Number too large at line 1, column 7");
+    assert_eq!(error("PRINT 0xFFFF_FFFF_FFFF_FFFF_FFFF_FFFF_FFFF_FFFF\n"), "Number too large at line 1, column 7");
+    compiles("PRINT 3.4e38\n");
+    // A double holds them both.
+    let options = CompileOptions::new().number_type(NumberType::F64);
+    assert!(compile_str("PRINT 1e39\nPRINT 0xFFFF_FFFF_FFFF_FFFF_FFFF_FFFF_FFFF_FFFF\n", &options).is_ok());
+    assert!(compile_str("PRINT 1e309\n", &options).is_err());
+}
+
+#[test]
+fn lower_case_keyword_gets_a_hint() {
+    assert_eq!(
+        error("LET x = 1\nif x > 0 THEN\nENDIF\n"),
+        "Invalid statement 'if' at line 2, column 1. Keywords are upper case: did you mean IF? \
+         (--dialect=case-insensitive accepts either)"
+    );
+    assert!(error("Let x = 1\n").contains("did you mean LET?"));
+    // Elsewhere a lower-case keyword is just a name.
+    compiles("LET print = 1\nPRINTLN print\n");
+}
+
+#[test]
+fn case_insensitive_dialect() {
+    let options = CompileOptions::new().dialect(Dialect::CaseInsensitive);
+    let source = "let x = 1\nWhile x < 3 repeat\nLet x = x + 1\nendwhile\nprintln x\n";
+    if let Err(d) = compile_str(source, &options) {
+        panic!("failed to compile:\n{}", d);
+    }
+    // Keywords in any case can't be names.
+    let d = compile_str("let print = 1\n", &options).unwrap_err();
+    assert_eq!(d.error.message, "Expected Ident, got Print at line 1, column 5");
+    assert!(compile_str(source, &CompileOptions::new().dialect(Dialect::Strict)).is_err());
+}
+
+#[test]
+fn identifiers_can_contain_underscores_and_digits() {
+    compiles("LET my_var = 1\nLET _x2 = my_var\nPRINTLN _x2\n");
+    assert_eq!(error("LET 2x = 1\n"), "Invalid character 'x' in number at line 1, column 6");
+}
@@ -0,0 +1,24 @@
+// passes.rs
+// -fdump-passes hands the IR back in the output instead of printing it.
+
+use ttrust::{compile_str, CompileOptions, Target};
+
+const SOURCE: &str = "INPUT a\nLET b = a * 2\nPRINTLN b\n";
+
+#[test]
+fn dumps_come_back_in_the_output() {
+    let options = CompileOptions::new().target(Target::Llvm).opt_level(2).pass("dump-passes");
+    let dumps = compile_str(SOURCE, &options).unwrap().pass_dumps;
+    for pass in ["ssa", "const-prop", "cse", "copy-prop", "dse", "out-of-ssa"] {
+        assert!(dumps.contains(&format!("; after {}\n", pass)), "{}", dumps);
+    }
+}
+
+#[test]
+fn nothing_is_dumped_unless_asked() {
+    let options = CompileOptions::new().target(Target::Llvm).opt_level(2);
+    assert_eq!(compile_str(SOURCE, &options).unwrap().pass_dumps, "");
+    // C doesn't go through the IR.
+    let options = CompileOptions::new().opt_level(2).pass("dump-passes");
+    assert_eq!(compile_str(SOURCE, &options).unwrap().pass_dumps, "");
+}
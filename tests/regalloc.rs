@@ -0,0 +1,79 @@
+// regalloc.rs
+// Live intervals and register assignments, read from --emit=regalloc.
+
+use std::fs;
+use ttrust::{compile_str, CompileOptions, EmitKind, NumberType};
+
+/// The allocation listing for `source`, without the IR after it
+fn allocation(source: &str, options: CompileOptions) -> String {
+    let code = compile_str(source, &options.emit(EmitKind::Regalloc)).unwrap().code;
+    code.split("\n\n").next().unwrap().to_string()
+}
+
+/// The interval and location listed for `value`
+fn line<'a>(listing: &'a str, value: &str) -> &'a str {
+    let found = listing.lines().find(|l| l.split_whitespace().next() == Some(value));
+    found.unwrap_or_else(|| panic!("no {} in\n{}", value, listing))
+}
+
+fn fib() -> String {
+    fs::read_to_string("examples/fib.teeny").unwrap()
+}
+
+/// 16 variables that are all live at once, then their sum
+fn many_values() -> String {
+    let names: Vec<String> = ('a'..='p').map(String::from).collect();
+    let mut source = String::new();
+    for (i, name) in names.iter().enumerate() {
+        source += &format!("LET {} = {}\n", name, i);
+    }
+    source + &format!("LET s = {}\nPRINTLN s\n", names.join(" + "))
+}
+
+#[test]
+fn loop_values_live_across_the_back_edge() {
+    let listing = allocation(&fib(), CompileOptions::new().opt_level(2));
+    // The loop variables are live from before the loop to its last
+    // instruction, and the loop prints while they are live.
+    for phi in ["2phi", "3phi", "4phi"] {
+        let l = line(&listing, phi);
+        assert!(l.contains(", 23]"), "{}", listing);
+        assert!(l.ends_with("(across call)"), "{}", listing);
+    }
+    // The loop condition is only live until the branch.
+    assert!(line(&listing, "%t1").starts_with("%t1      [13, 14]"), "{}", listing);
+}
+
+#[test]
+fn values_are_spilled_when_registers_run_out() {
+    let listing = allocation(&many_values(), CompileOptions::new());
+    assert!(listing.starts_with("; x86-64: 14 registers, 12 bytes of stack\n"), "{}", listing);
+    for spilled in ["n", "o", "p"] {
+        assert!(line(&listing, spilled).contains("[sp+"), "{}", listing);
+    }
+    // Values that die early are left in registers.
+    assert!(line(&listing, "a").ends_with("xmm2"), "{}", listing);
+}
+
+#[test]
+fn riscv_keeps_values_across_calls_in_registers() {
+    let x86 = allocation(&fib(), CompileOptions::new().opt_level(2));
+    let riscv = allocation(&fib(), CompileOptions::new().opt_level(2).arch("riscv64"));
+    // No xmm register survives a call, so x86-64 spills the loop variables.
+    assert!(line(&x86, "2phi").contains("[sp+8]"), "{}", x86);
+    assert!(line(&riscv, "2phi").contains("fs1"), "{}", riscv);
+    assert!(x86.starts_with("; x86-64: 2 registers, 32 bytes of stack\n"), "{}", x86);
+    assert!(riscv.starts_with("; riscv64: 8 registers, 4 bytes of stack\n"), "{}", riscv);
+    // INPUT needs its variable in memory on either machine.
+    assert!(line(&riscv, "nums").contains("[sp+0]"), "{}", riscv);
+}
+
+#[test]
+fn f64_values_take_eight_byte_slots() {
+    let options = CompileOptions::new().opt_level(2).number_type(NumberType::F64);
+    let listing = allocation(&fib(), options);
+    assert!(listing.starts_with("; x86-64: 2 registers, 64 bytes of stack\n"), "{}", listing);
+    for (value, slot) in [("nums", "[sp+0]"), ("%t6", "[sp+8]"), ("2phi", "[sp+16]"), ("%t4", "[sp+56]")] {
+        assert!(line(&listing, value).contains(slot), "{}", listing);
+    }
+}
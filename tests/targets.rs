@@ -0,0 +1,230 @@
+// targets.rs
+// Every target has to print what the C target prints. Each example is
+// compiled for each target, run on the same inputs and compared with the C
+// build, with f32 and with f64 values. A target whose tools aren't
+// installed is skipped.
+
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use ttrust::{compile_str, CompileOptions, NumberType, Target};
+
+/// Enough numbers for every example. The first is the count that
+/// average, fib, goto and minmax ask for.
+const INPUT: &str = "4\n1.5\n-2\n7\n0.25\n3\n2\n1\n0\n5\n6\n-1\n8\n9\n10\n11\n12\n13\n14\n15\n16\n";
+
+/// Input that scanf reads in unusual ways: hexadecimal, infinities, NaNs,
+/// and words that only start like numbers, which are skipped. The NaNs are
+/// negative like the ones arithmetic makes, since which NaN comes out of
+/// an operation on two depends on how the C compiler orders the operands.
+const UNUSUAL_INPUT: &str = "3\n0x10\n-inf\n-nan 0x1.8p1\n+Infinity -NaN\n0x.8 1e999 0xg in 7\n\
+    0X1P-2 infx 2.5\n-0x1p-3 1e 00x1 + 8\n0x1p99999 9 10 11 12 13 14 15 16\n";
+
+/// Runs WebAssembly with WASI under node
+const WASM_RUNNER: &str = "\
+import { readFileSync } from 'node:fs';
+import { WASI } from 'node:wasi';
+const wasi = new WASI({ version: 'preview1', args: [], env: {} });
+const mod = await WebAssembly.compile(readFileSync(process.argv[2]));
+wasi.start(await WebAssembly.instantiate(mod, wasi.getImportObject()));
+";
+
+fn installed(tool: &str) -> bool {
+    Command::new(tool).arg("--version").stdout(Stdio::null()).stderr(Stdio::null()).status().is_ok()
+}
+
+/// The major version of an LLVM tool
+fn llvm_version(tool: &str) -> Option<u32> {
+    let output = Command::new(tool).arg("--version").output().ok()?;
+    let text = String::from_utf8_lossy(&output.stdout);
+    let version = text.split("LLVM version ").nth(1)?;
+    version.split('.').next()?.trim().parse().ok()
+}
+
+/// Run a command to completion, failing the test if it fails
+fn run(command: &mut Command, input: &str) -> String {
+    let mut child = command.stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().unwrap();
+    // A program that doesn't read all of its input may exit first.
+    let _ = child.stdin.take().unwrap().write_all(input.as_bytes());
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{:?} failed:\n{}", command, String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// Compile, build if the target needs it, and run once with each of
+/// `inputs`. None if the tools for the target are missing.
+fn outputs(target: Target, source: &str, options: &CompileOptions, base: &Path, inputs: &[&str]) -> Option<Vec<String>> {
+    let options = options.clone().target(target);
+    let code = compile_str(source, &options).unwrap().code;
+    let file = base.with_extension(Path::new(target.default_path()).extension().unwrap());
+    fs::write(&file, code).unwrap();
+    let exe = base.with_extension(format!("{:?}.exe", target));
+    let mut command = match target {
+        Target::C => {
+            run(Command::new("cc").arg("-o").arg(&exe).arg(&file).arg("-lm"), "");
+            Command::new(&exe)
+        }
+        Target::Llvm if installed("lli") => {
+            let mut lli = Command::new("lli");
+            // LLVM 14 and earlier only read `ptr` with opaque pointers on.
+            if llvm_version("lli").is_some_and(|v| v < 15) {
+                lli.arg("-opaque-pointers");
+            }
+            lli.arg(&file);
+            lli
+        }
+        Target::Wasm if installed("wat2wasm") && installed("node") => {
+            let wasm = base.with_extension("wasm");
+            run(Command::new("wat2wasm").arg(&file).arg("-o").arg(&wasm), "");
+            let runner = base.with_file_name("run-wasm.mjs");
+            fs::write(&runner, WASM_RUNNER).unwrap();
+            let mut node = Command::new("node");
+            node.arg("--no-warnings").arg(&runner).arg(&wasm);
+            node
+        }
+        Target::Qbe if installed("qbe") => {
+            let asm = base.with_extension("s");
+            run(Command::new("qbe").arg("-o").arg(&asm).arg(&file), "");
+            run(Command::new("cc").arg("-o").arg(&exe).arg(&asm), "");
+            Command::new(&exe)
+        }
+        Target::Rust if installed("rustc") => {
+            run(Command::new("rustc").arg("-O").arg("-o").arg(&exe).arg(&file), "");
+            Command::new(&exe)
+        }
+        Target::Js if installed("node") => {
+            let mut node = Command::new("node");
+            node.arg(&file);
+            node
+        }
+        Target::Python if installed("python3") => {
+            let mut python = Command::new("python3");
+            python.arg(&file);
+            python
+        }
+        _ => return None,
+    };
+    Some(inputs.iter().map(|input| run(&mut command, input)).collect())
+}
+
+/// The output for INPUT alone
+fn output(target: Target, source: &str, options: &CompileOptions, base: &Path) -> Option<String> {
+    outputs(target, source, options, base, &[INPUT]).map(|mut o| o.remove(0))
+}
+
+#[test]
+fn every_target_prints_the_same() {
+    if !installed("cc") {
+        eprintln!("cc not found; skipping");
+        return;
+    }
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("targets");
+    fs::create_dir_all(&dir).unwrap();
+    let targets = [Target::Llvm, Target::Wasm, Target::Qbe, Target::Rust, Target::Js, Target::Python];
+    for entry in fs::read_dir("examples").unwrap() {
+        let path = entry.unwrap().path();
+        let source = fs::read_to_string(&path).unwrap();
+        let name = path.file_stem().unwrap().to_str().unwrap();
+        for number_type in [NumberType::F32, NumberType::F64] {
+            for level in [0, 2] {
+                let options = CompileOptions::new().opt_level(level).number_type(number_type);
+                let base = dir.join(format!("{}-{:?}-O{}", name, number_type, level));
+                let expected = outputs(Target::C, &source, &options, &base, &[INPUT, UNUSUAL_INPUT]).unwrap();
+                for &target in targets.iter() {
+                    match outputs(target, &source, &options, &base, &[INPUT, UNUSUAL_INPUT]) {
+                        Some(actual) => {
+                            for (actual, expected) in actual.iter().zip(expected.iter()) {
+                                // JavaScript can't tell NaNs apart by sign.
+                                let (actual, expected) = match target {
+                                    Target::Js => (actual.replace("-nan", "nan"), expected.replace("-nan", "nan")),
+                                    _ => (actual.clone(), expected.clone()),
+                                };
+                                assert_eq!(actual, expected, "{:?} on {} with {:?} at -O{}", target, name, number_type, level)
+                            }
+                        }
+                        None => eprintln!("tools for {:?} not found; skipping", target),
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn js_prints_before_stdin_is_closed() {
+    if !installed("node") {
+        eprintln!("node not found; skipping");
+        return;
+    }
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("targets");
+    fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("prompt.js");
+    let options = CompileOptions::new().target(Target::Js);
+    fs::write(&file, compile_str("PRINTLN \"ready\"\nINPUT x\nPRINTLN x\n", &options).unwrap().code).unwrap();
+    let mut child = Command::new("node").arg(&file).stdin(Stdio::piped()).stdout(Stdio::piped()).spawn().unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    let mut stdout = child.stdout.take().unwrap();
+    // The prompt has to arrive while stdin is still open.
+    let (send, receive) = mpsc::channel();
+    thread::spawn(move || {
+        let mut prompt = [0; 6];
+        let result = stdout.read_exact(&mut prompt).map(|_| prompt);
+        send.send((result, stdout)).unwrap();
+    });
+    let Ok((prompt, mut stdout)) = receive.recv_timeout(Duration::from_secs(10)) else {
+        child.kill().unwrap();
+        panic!("no output before stdin was closed");
+    };
+    assert_eq!(&prompt.unwrap(), b"ready\n");
+    stdin.write_all(b"3\n").unwrap();
+    drop(stdin);
+    let mut rest = String::new();
+    stdout.read_to_string(&mut rest).unwrap();
+    assert!(child.wait().unwrap().success());
+    assert_eq!(rest, "3.00\n");
+}
+
+#[test]
+fn python_runs_programs_with_many_blocks() {
+    if !installed("python3") {
+        eprintln!("python3 not found; skipping");
+        return;
+    }
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("targets");
+    fs::create_dir_all(&dir).unwrap();
+    // Each IF adds two blocks, far more than CPython can compile as one
+    // elif chain.
+    let source = format!("LET n = 0\n{}PRINTLN n\n", "IF n < 100000 THEN\nLET n = n + 1\nENDIF\n".repeat(1500));
+    let output = output(Target::Python, &source, &CompileOptions::new(), &dir.join("many-blocks")).unwrap();
+    assert_eq!(output, "1500.00\n");
+}
+
+#[test]
+fn nan_signs_match_c() {
+    if !installed("cc") {
+        eprintln!("cc not found; skipping");
+        return;
+    }
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("targets");
+    fs::create_dir_all(&dir).unwrap();
+    // 0 / 0 is a negative NaN on x86, and negating it gives a positive one.
+    // scanf reads "nan" as a positive NaN. JavaScript engines don't keep the
+    // sign of NaN, so JS isn't compared.
+    let source = "LET z = 0\nLET z = z / z\nPRINTLN z\nPRINTLN -z\nLET w = -z\nPRINTLN w + 1\n\
+                  INPUT a\nPRINTLN a\nPRINTLN -a\n";
+    for level in [0, 2] {
+        let options = CompileOptions::new().opt_level(level);
+        let base = dir.join(format!("nan-O{}", level));
+        let expected = outputs(Target::C, source, &options, &base, &["nan"]).unwrap();
+        assert_eq!(expected, ["-nan\nnan\nnan\nnan\n-nan\n"]);
+        for target in [Target::Llvm, Target::Wasm, Target::Rust, Target::Python] {
+            if let Some(actual) = outputs(target, source, &options, &base, &["nan"]) {
+                assert_eq!(actual, expected, "{:?} at -O{}", target, level);
+            }
+        }
+    }
+}